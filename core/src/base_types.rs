@@ -34,6 +34,12 @@ pub struct Transform {
     pub mat: Mat4,
 }
 
+#[derive(Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Position,
+    pub max: Position,
+}
+
 impl Position {
     pub fn new(x: f32, y: f32, z: f32) -> Position {
         Position { vec: Vec3(x, y, z) }
@@ -167,13 +173,7 @@ impl LdrColor {
     }
 
     fn in_range(f: f32) -> f32 {
-        if f < 0f32 {
-            0f32
-        } else if f > 1f32 {
-            1f32
-        } else {
-            f
-        }
+        f.clamp(0f32, 1f32)
     }
 }
 
@@ -195,6 +195,47 @@ impl HdrColor {
     }
 }
 
+impl HdrColor {
+    pub fn luminance(self) -> f32 {
+        0.2126f32 * self.r + 0.7152f32 * self.g + 0.0722f32 * self.b
+    }
+}
+
+impl BoundingBox {
+    pub fn from_position(position: Position) -> BoundingBox {
+        BoundingBox {
+            min: position,
+            max: position,
+        }
+    }
+
+    pub fn union(self, rhs: BoundingBox) -> BoundingBox {
+        let Vec3(a0, a1, a2) = self.min.vec;
+        let Vec3(b0, b1, b2) = rhs.min.vec;
+        let Vec3(c0, c1, c2) = self.max.vec;
+        let Vec3(d0, d1, d2) = rhs.max.vec;
+        BoundingBox {
+            min: Position::new(a0.min(b0), a1.min(b1), a2.min(b2)),
+            max: Position::new(c0.max(d0), c1.max(d1), c2.max(d2)),
+        }
+    }
+
+    pub fn diagonal(self) -> Movement {
+        self.max - self.min
+    }
+
+    pub fn center(self) -> Position {
+        self.min + self.diagonal() * 0.5f32
+    }
+
+    pub fn contains(self, position: Position) -> bool {
+        let Vec3(x, y, z) = position.vec;
+        (self.min.vec.0..=self.max.vec.0).contains(&x)
+            && (self.min.vec.1..=self.max.vec.1).contains(&y)
+            && (self.min.vec.2..=self.max.vec.2).contains(&z)
+    }
+}
+
 impl std::ops::Add<Movement> for Position {
     type Output = Position;
 
@@ -218,7 +259,7 @@ impl std::ops::Sub<Position> for Position {
 impl Position {
     pub fn apply(self, transform: Transform) -> Position {
         Position {
-            vec: (Vec4::from_movement(self.vec) * transform.mat).into(),
+            vec: (Vec4::from_position(self.vec) * transform.mat).into(),
         }
     }
 }
@@ -0,0 +1,1614 @@
+use crate::{
+    base_types::{Direction, HdrColor, LdrColor},
+    math::Vec3,
+    EPSILON,
+};
+
+#[derive(Clone, Copy)]
+pub struct ShadingFrame {
+    /// Normal of the actual surface, pointing out of the object.
+    pub real_normal: Direction,
+    /// Normal used for shading.
+    pub normal: Direction,
+    /// Direction along the surface of `roughness_u`, perpendicular to
+    /// `normal`.
+    pub tangent: Direction,
+}
+
+impl ShadingFrame {
+    /// Makes `tangent` perpendicular to `normal`, or picks an arbitrary
+    /// tangent if they are parallel.
+    pub fn new(real_normal: Direction, normal: Direction, tangent: Direction) -> ShadingFrame {
+        let projected = tangent.vec - normal.vec * tangent.cos_angle_between(normal);
+        let tangent = if projected.length_squared() > EPSILON * EPSILON {
+            Direction {
+                vec: projected.normalize(),
+            }
+        } else {
+            local_to_world(normal, Vec3(1f32, 0f32, 0f32))
+        };
+        ShadingFrame {
+            real_normal,
+            normal,
+            tangent,
+        }
+    }
+
+    pub fn bitangent(self) -> Direction {
+        self.normal.perpendicular_to(self.tangent)
+    }
+
+    /// Coordinates of `direction` along the tangent, bitangent and normal.
+    pub fn to_local(self, direction: Direction) -> Vec3 {
+        Vec3(
+            direction.cos_angle_between(self.tangent),
+            direction.cos_angle_between(self.bitangent()),
+            direction.cos_angle_between(self.normal),
+        )
+    }
+
+    pub fn to_world(self, local: Vec3) -> Direction {
+        Direction {
+            vec: (self.tangent.vec * local.0
+                + self.bitangent().vec * local.1
+                + self.normal.vec * local.2)
+                .normalize(),
+        }
+    }
+}
+
+pub struct BsdfSample {
+    pub surface_to_light: Direction,
+    /// Same as `Bsdf::evaluate` for the sampled direction, or for specular
+    /// samples the weight of the whole lobe times `pdf`.
+    pub value: HdrColor,
+    pub pdf: f32,
+    /// Whether the direction was chosen from a delta distribution, which
+    /// `evaluate` and `pdf` cannot describe.
+    pub specular: bool,
+}
+
+/// How a surface scatters light. Directions point away from the surface and
+/// `evaluate` includes the cosine between the normal and the light.
+pub trait Bsdf {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor;
+
+    /// `u_lobe` picks among the lobes, `u` a direction within the lobe.
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample>;
+
+    /// Solid-angle density with which `sample` returns `surface_to_light`.
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32;
+
+    /// Fraction of uniform ambient light reflected towards the viewer.
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor;
+
+    /// Whether light from behind the surface passes through it, so that
+    /// lights on both sides need to be considered.
+    fn transmits(&self) -> bool {
+        false
+    }
+
+    /// Perfectly specular directions light arrives from, with their weights.
+    fn specular_lobes(
+        &self,
+        _surface_to_view: Direction,
+        _frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        Vec::new()
+    }
+
+    /// Radiance the surface gives off by itself.
+    fn emission(&self) -> HdrColor {
+        HdrColor::new(0f32, 0f32, 0f32)
+    }
+
+    /// The medium below a translucent surface, which light not reflected by
+    /// the BSDF enters and wanders through before leaving again.
+    fn subsurface(&self) -> Option<SubsurfaceMedium> {
+        None
+    }
+}
+
+const fn linear_rgb(r: f32, g: f32, b: f32) -> LdrColor {
+    LdrColor { r, g, b }
+}
+
+// Linear sRGB reflectance at normal incidence, from measured spectral data.
+pub const F0_NORMAL: LdrColor = linear_rgb(0.04f32, 0.04f32, 0.04f32);
+pub const F0_GOLD: LdrColor = linear_rgb(1f32, 0.766f32, 0.336f32);
+pub const F0_SILVER: LdrColor = linear_rgb(0.972f32, 0.96f32, 0.915f32);
+pub const F0_COPPER: LdrColor = linear_rgb(0.955f32, 0.638f32, 0.538f32);
+pub const F0_ALUMINUM: LdrColor = linear_rgb(0.913f32, 0.922f32, 0.924f32);
+pub const F0_IRON: LdrColor = linear_rgb(0.562f32, 0.565f32, 0.578f32);
+pub const F0_CHROMIUM: LdrColor = linear_rgb(0.549f32, 0.556f32, 0.554f32);
+pub const F0_NICKEL: LdrColor = linear_rgb(0.66f32, 0.609f32, 0.526f32);
+pub const F0_TITANIUM: LdrColor = linear_rgb(0.542f32, 0.497f32, 0.449f32);
+pub const F0_PLATINUM: LdrColor = linear_rgb(0.673f32, 0.637f32, 0.585f32);
+pub const F0_ZINC: LdrColor = linear_rgb(0.664f32, 0.824f32, 0.85f32);
+
+/// Reflectance at normal incidence of a conductor with complex index of
+/// refraction `n + ik` per channel, seen from air.
+pub fn f0_from_complex_ior(n: HdrColor, k: HdrColor) -> LdrColor {
+    let reflectance =
+        |n: f32, k: f32| ((n - 1f32) * (n - 1f32) + k * k) / ((n + 1f32) * (n + 1f32) + k * k);
+    LdrColor::new(
+        reflectance(n.r, k.r),
+        reflectance(n.g, k.g),
+        reflectance(n.b, k.b),
+    )
+}
+
+/// A transparent film, like soap or oil, whose reflections interfere.
+#[derive(Clone, Copy)]
+pub struct ThinFilm {
+    /// In nanometres.
+    pub thickness: f32,
+    pub ior: f32,
+}
+
+/// Wavelengths in nanometres standing in for the red, green and blue
+/// channels.
+const RGB_WAVELENGTHS: [f32; 3] = [650f32, 510f32, 475f32];
+
+/// Reflectance of a surface with reflectance `f0` at normal incidence under
+/// a thin film, summing the waves reflected at both of the film's
+/// interfaces as in the Airy formula.
+pub(crate) fn fresnel_thin_film(cos_theta: f32, f0: LdrColor, film: ThinFilm) -> HdrColor {
+    let cos_theta = cos_theta.clamp(0f32, 1f32);
+    let sin_theta_film2 = (1f32 - cos_theta * cos_theta) / (film.ior * film.ior);
+    let cos_theta_film = (1f32 - sin_theta_film2).max(0f32).sqrt();
+    let reflectance = |f0: f32, wavelength: f32| {
+        // The substrate is treated as a dielectric matching `f0`.
+        let sqrt_f0 = f0.clamp(0f32, 0.99f32).sqrt();
+        let substrate_ior = (1f32 + sqrt_f0) / (1f32 - sqrt_f0);
+        let sin_theta_substrate2 =
+            sin_theta_film2 * (film.ior * film.ior) / (substrate_ior * substrate_ior);
+        let cos_theta_substrate = (1f32 - sin_theta_substrate2).max(0f32).sqrt();
+        let phase =
+            4f32 * std::f32::consts::PI * film.ior * film.thickness * cos_theta_film / wavelength;
+        let airy = |outer: f32, inner: f32| {
+            let numerator = outer * outer + inner * inner + 2f32 * outer * inner * phase.cos();
+            let denominator =
+                1f32 + outer * outer * inner * inner + 2f32 * outer * inner * phase.cos();
+            numerator / denominator
+        };
+        let parallel = |n_i: f32, cos_i: f32, n_t: f32, cos_t: f32| {
+            (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t)
+        };
+        let perpendicular = |n_i: f32, cos_i: f32, n_t: f32, cos_t: f32| {
+            (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t)
+        };
+        let r = airy(
+            parallel(1f32, cos_theta, film.ior, cos_theta_film),
+            parallel(film.ior, cos_theta_film, substrate_ior, cos_theta_substrate),
+        ) + airy(
+            perpendicular(1f32, cos_theta, film.ior, cos_theta_film),
+            perpendicular(film.ior, cos_theta_film, substrate_ior, cos_theta_substrate),
+        );
+        r / 2f32
+    };
+    let [red, green, blue] = RGB_WAVELENGTHS;
+    HdrColor::new(
+        reflectance(f0.r, red),
+        reflectance(f0.g, green),
+        reflectance(f0.b, blue),
+    )
+}
+
+pub(crate) fn fresnel_schlick(cos_theta: f32, f0: LdrColor) -> HdrColor {
+    let weight = (1f32 - cos_theta).powf(5f32);
+    HdrColor::new(
+        f0.r + (1f32 - f0.r) * weight,
+        f0.g + (1f32 - f0.g) * weight,
+        f0.b + (1f32 - f0.b) * weight,
+    )
+}
+
+/// GGX width for a perceptual roughness, kept above zero so that perfectly
+/// smooth surfaces stay finite.
+fn ggx_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(0.001f32)
+}
+
+pub(crate) fn ggx_ndf(n: Direction, h: Direction, roughness: f32) -> f32 {
+    let alpha = ggx_alpha(roughness);
+    let alpha2 = alpha * alpha;
+    let cos_n_h = n.cos_angle_between(h);
+    let cos_n_h2 = cos_n_h * cos_n_h;
+    let denom = cos_n_h2 * alpha2 + (1f32 - cos_n_h2);
+    alpha2 / (std::f32::consts::PI * denom * denom)
+}
+
+/// GGX distribution stretched by `roughness_u` along the frame's tangent
+/// and by `roughness_v` along its bitangent.
+pub(crate) fn ggx_ndf_anisotropic(
+    frame: ShadingFrame,
+    h: Direction,
+    roughness_u: f32,
+    roughness_v: f32,
+) -> f32 {
+    let (alpha_u, alpha_v) = (ggx_alpha(roughness_u), ggx_alpha(roughness_v));
+    let Vec3(x, y, z) = frame.to_local(h);
+    if z <= 0f32 {
+        return 0f32;
+    }
+    let denom = x * x / (alpha_u * alpha_u) + y * y / (alpha_v * alpha_v) + z * z;
+    1f32 / (std::f32::consts::PI * alpha_u * alpha_v * denom * denom)
+}
+
+/// Schlick-GGX shadowing and masking, using the roughness projected onto
+/// the plane of each direction for anisotropic surfaces.
+pub(crate) fn geometric_attenuation(
+    frame: ShadingFrame,
+    v: Direction,
+    l: Direction,
+    roughness_u: f32,
+    roughness_v: f32,
+) -> f32 {
+    let g1 = |w: Direction| {
+        let Vec3(x, y, z) = frame.to_local(w);
+        let radial2 = x * x + y * y;
+        let roughness = if radial2 > 0f32 {
+            let (alpha_u, alpha_v) = (ggx_alpha(roughness_u), ggx_alpha(roughness_v));
+            ((alpha_u * alpha_u * x * x + alpha_v * alpha_v * y * y) / radial2)
+                .sqrt()
+                .sqrt()
+        } else {
+            (roughness_u * roughness_v).sqrt()
+        };
+        let k = (roughness + 1f32) * (roughness + 1f32) / 8f32;
+        z / (z * (1f32 - k) + k)
+    };
+    g1(v) * g1(l)
+}
+
+/// Samples a microfacet normal with density
+/// `ggx_ndf_anisotropic(frame, h) * cos(n, h)`.
+pub(crate) fn sample_ggx_ndf_anisotropic(
+    frame: ShadingFrame,
+    roughness_u: f32,
+    roughness_v: f32,
+    u: (f32, f32),
+) -> Direction {
+    let (alpha_u, alpha_v) = (ggx_alpha(roughness_u), ggx_alpha(roughness_v));
+    let angle = 2f32 * std::f32::consts::PI * u.1;
+    let phi = (alpha_v * angle.sin()).atan2(alpha_u * angle.cos());
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let tan_theta2 = u.0
+        / (1f32 - u.0).max(f32::MIN_POSITIVE)
+        / (cos_phi * cos_phi / (alpha_u * alpha_u) + sin_phi * sin_phi / (alpha_v * alpha_v));
+    let cos_theta = 1f32 / (1f32 + tan_theta2).sqrt();
+    let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+    frame.to_world(Vec3(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta))
+}
+
+/// Samples a microfacet normal with density `ggx_ndf(n, h) * cos(n, h)`.
+pub(crate) fn sample_ggx_ndf(n: Direction, roughness: f32, u: (f32, f32)) -> Direction {
+    let alpha = ggx_alpha(roughness);
+    let cos_theta2 = (1f32 - u.0) / (u.0 * (alpha * alpha - 1f32) + 1f32);
+    let cos_theta = cos_theta2.sqrt();
+    let sin_theta = (1f32 - cos_theta2).max(0f32).sqrt();
+    let phi = 2f32 * std::f32::consts::PI * u.1;
+    local_to_world(
+        n,
+        Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+    )
+}
+
+pub(crate) fn sample_cosine_hemisphere(n: Direction, u: (f32, f32)) -> Direction {
+    let r = u.0.sqrt();
+    let phi = 2f32 * std::f32::consts::PI * u.1;
+    local_to_world(
+        n,
+        Vec3(r * phi.cos(), r * phi.sin(), (1f32 - u.0).max(0f32).sqrt()),
+    )
+}
+
+pub(crate) fn sample_uniform_sphere(u: (f32, f32)) -> Direction {
+    let cos_theta = 1f32 - 2f32 * u.0;
+    let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+    let phi = 2f32 * std::f32::consts::PI * u.1;
+    Direction {
+        vec: Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+    }
+}
+
+/// Rotates `local`, given in a frame whose z axis is `n`, into world space.
+pub(crate) fn local_to_world(n: Direction, local: Vec3) -> Direction {
+    // Duff et al., "Building an Orthonormal Basis, Revisited".
+    let Vec3(x, y, z) = n.vec;
+    let sign = 1f32.copysign(z);
+    let a = -1f32 / (sign + z);
+    let b = x * y * a;
+    let tangent = Vec3(1f32 + sign * x * x * a, sign * b, -sign * x);
+    let bitangent = Vec3(b, sign + y * y * a, -y);
+    Direction {
+        vec: (tangent * local.0 + bitangent * local.1 + n.vec * local.2).normalize(),
+    }
+}
+
+fn reflect(v: Direction, n: Direction) -> Direction {
+    Direction {
+        vec: n.vec * (2f32 * v.cos_angle_between(n)) - v.vec,
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct CookTorrance {
+    pub albedo: LdrColor,
+    /// Roughness along the surface tangent.
+    pub roughness_u: f32,
+    /// Roughness across the surface tangent.
+    pub roughness_v: f32,
+    pub f0: LdrColor,
+    pub thin_film: Option<ThinFilm>,
+}
+
+/// Cook-Torrance surfaces at most this rough are traced as mirrors, so that
+/// polished metals reflect the scene rather than just the lights.
+pub const MIRROR_ROUGHNESS: f32 = 0.05f32;
+
+impl CookTorrance {
+    fn is_mirror(&self) -> bool {
+        self.roughness_u.max(self.roughness_v) <= MIRROR_ROUGHNESS
+    }
+
+    fn fresnel(&self, cos_theta: f32) -> HdrColor {
+        match self.thin_film {
+            Some(film) => fresnel_thin_film(cos_theta, self.f0, film),
+            None => fresnel_schlick(cos_theta, self.f0),
+        }
+    }
+
+    /// Probability of sampling the specular lobe rather than the diffuse one.
+    fn specular_probability(&self, surface_to_view: Direction, normal: Direction) -> f32 {
+        let f = self.fresnel(normal.cos_angle_between(surface_to_view).max(0f32));
+        let specular = f.luminance();
+        let diffuse = (self.albedo * HdrColor::new(1f32 - f.r, 1f32 - f.g, 1f32 - f.b)).luminance();
+        if specular + diffuse > 0f32 {
+            specular / (specular + diffuse)
+        } else {
+            0.5f32
+        }
+    }
+}
+
+impl Bsdf for CookTorrance {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        let (v, l, n) = (surface_to_view, surface_to_light, frame.normal);
+        let cos_n_v = n.cos_angle_between(v);
+        let cos_n_l = n.cos_angle_between(l);
+        if cos_n_v <= 0f32 || cos_n_l <= 0f32 {
+            return HdrColor::new(0f32, 0f32, 0f32);
+        }
+        let h = Direction::from_directions([v, l]);
+        let d = ggx_ndf_anisotropic(frame, h, self.roughness_u, self.roughness_v);
+        let f = self.fresnel(h.cos_angle_between(v));
+        let g = geometric_attenuation(frame, v, l, self.roughness_u, self.roughness_v);
+        let specular = f * ((d * g) / (4f32 * cos_n_v * cos_n_l));
+        // Light not reflected at the interface is scattered diffusely.
+        let diffuse = self.albedo
+            * HdrColor::new(1f32 - f.r, 1f32 - f.g, 1f32 - f.b)
+            * (1f32 / std::f32::consts::PI);
+        (diffuse + specular) * cos_n_l
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        let normal = frame.normal;
+        let surface_to_light = if u_lobe < self.specular_probability(surface_to_view, normal) {
+            let h = sample_ggx_ndf_anisotropic(frame, self.roughness_u, self.roughness_v, u);
+            reflect(surface_to_view, h)
+        } else {
+            sample_cosine_hemisphere(normal, u)
+        };
+        let pdf = self.pdf(surface_to_view, surface_to_light, frame);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            surface_to_light,
+            value: self.evaluate(surface_to_view, surface_to_light, frame),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        let normal = frame.normal;
+        let cos_n_l = normal.cos_angle_between(surface_to_light);
+        if cos_n_l <= 0f32 || normal.cos_angle_between(surface_to_view) <= 0f32 {
+            return 0f32;
+        }
+        let h = Direction::from_directions([surface_to_view, surface_to_light]);
+        let specular_pdf = ggx_ndf_anisotropic(frame, h, self.roughness_u, self.roughness_v)
+            * normal.cos_angle_between(h)
+            / (4f32 * h.cos_angle_between(surface_to_view).abs());
+        let diffuse_pdf = cos_n_l / std::f32::consts::PI;
+        let specular_probability = self.specular_probability(surface_to_view, normal);
+        specular_probability * specular_pdf + (1f32 - specular_probability) * diffuse_pdf
+    }
+
+    fn reflectance(&self, _surface_to_view: Direction, _frame: ShadingFrame) -> HdrColor {
+        let f = self.fresnel(1f32);
+        // A mirror reflects its surroundings through its specular lobe.
+        let specular = if self.is_mirror() { 0f32 } else { 1f32 };
+        HdrColor::new(
+            self.albedo.r * (1f32 - f.r) + f.r * specular,
+            self.albedo.g * (1f32 - f.g) + f.g * specular,
+            self.albedo.b * (1f32 - f.b) + f.b * specular,
+        )
+    }
+
+    fn specular_lobes(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        let cos_n_v = frame.normal.cos_angle_between(surface_to_view);
+        if !self.is_mirror() || cos_n_v <= 0f32 {
+            return Vec::new();
+        }
+        vec![(
+            reflect(surface_to_view, frame.normal),
+            self.fresnel(cos_n_v),
+        )]
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface, where `eta`
+/// is the index of refraction on the far side over that on the near side.
+pub(crate) fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0f32, 1f32);
+    let sin_theta_t2 = (1f32 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin_theta_t2 >= 1f32 {
+        return 1f32;
+    }
+    let cos_theta_t = (1f32 - sin_theta_t2).sqrt();
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2f32
+}
+
+/// Direction `v` refracts into through a surface with normal `n` on the
+/// same side as `v`, or `None` on total internal reflection.
+pub(crate) fn refract(v: Direction, n: Direction, eta: f32) -> Option<Direction> {
+    let cos_theta_i = v.cos_angle_between(n);
+    let sin_theta_t2 = (1f32 - cos_theta_i * cos_theta_i).max(0f32) / (eta * eta);
+    if sin_theta_t2 >= 1f32 {
+        return None;
+    }
+    let cos_theta_t = (1f32 - sin_theta_t2).sqrt();
+    Some(Direction {
+        vec: (v.vec * -1f32 / eta + n.vec * (cos_theta_i / eta - cos_theta_t)).normalize(),
+    })
+}
+
+/// Smooth glass or liquid. All light is either mirrored or refracted, so it
+/// only reaches the viewer through `specular_lobes` and `sample`.
+#[derive(Clone, Copy)]
+pub struct Dielectric {
+    pub ior: f32,
+    /// Colour applied to light each time it crosses the surface.
+    pub transmittance: LdrColor,
+}
+
+impl Dielectric {
+    /// Normal facing the viewer and relative index of refraction across the
+    /// surface, depending on whether the viewer is inside the object.
+    fn orient(&self, surface_to_view: Direction, frame: ShadingFrame) -> (Direction, f32) {
+        if surface_to_view.cos_angle_between(frame.real_normal) >= 0f32 {
+            (frame.normal, self.ior)
+        } else {
+            (-frame.normal, 1f32 / self.ior)
+        }
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn evaluate(
+        &self,
+        _surface_to_view: Direction,
+        _surface_to_light: Direction,
+        _frame: ShadingFrame,
+    ) -> HdrColor {
+        HdrColor::new(0f32, 0f32, 0f32)
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        _u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        let lobes = self.specular_lobes(surface_to_view, frame);
+        let total = lobes
+            .iter()
+            .map(|(_, weight)| weight.luminance())
+            .sum::<f32>();
+        let mut u = u_lobe * total;
+        for (surface_to_light, weight) in lobes {
+            let probability = weight.luminance();
+            if u < probability || probability == total {
+                return Some(BsdfSample {
+                    surface_to_light,
+                    value: weight * (probability / total),
+                    pdf: probability / total,
+                    specular: true,
+                });
+            }
+            u -= probability;
+        }
+        None
+    }
+
+    fn pdf(
+        &self,
+        _surface_to_view: Direction,
+        _surface_to_light: Direction,
+        _frame: ShadingFrame,
+    ) -> f32 {
+        0f32
+    }
+
+    fn reflectance(&self, _surface_to_view: Direction, _frame: ShadingFrame) -> HdrColor {
+        HdrColor::new(0f32, 0f32, 0f32)
+    }
+
+    fn specular_lobes(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        let (normal, eta) = self.orient(surface_to_view, frame);
+        let f = fresnel_dielectric(surface_to_view.cos_angle_between(normal), eta);
+        let mut lobes = vec![(reflect(surface_to_view, normal), HdrColor::new(f, f, f))];
+        if let Some(refracted) = refract(surface_to_view, normal, eta) {
+            // Radiance is compressed into a smaller solid angle on the
+            // denser side of the interface.
+            lobes.push((refracted, self.transmittance * ((1f32 - f) / (eta * eta))));
+        }
+        lobes
+    }
+}
+
+/// Smith's masking function for the GGX distribution.
+pub(crate) fn smith_g1_ggx(n: Direction, w: Direction, roughness: f32) -> f32 {
+    let alpha = ggx_alpha(roughness);
+    let cos_n_w = n.cos_angle_between(w).abs();
+    2f32 * cos_n_w / (cos_n_w + (alpha * alpha + (1f32 - alpha * alpha) * cos_n_w * cos_n_w).sqrt())
+}
+
+/// Frosted glass: a GGX microfacet surface that both reflects and
+/// transmits, following Walter et al., "Microfacet Models for Refraction
+/// through Rough Surfaces".
+#[derive(Clone, Copy)]
+pub struct RoughDielectric {
+    pub ior: f32,
+    pub roughness: f32,
+    /// Colour applied to light each time it crosses the surface.
+    pub transmittance: LdrColor,
+}
+
+impl RoughDielectric {
+    /// Normal on the viewer's side and relative index of refraction across
+    /// the surface, depending on whether the viewer is inside the object.
+    fn orient(&self, surface_to_view: Direction, frame: ShadingFrame) -> (Direction, f32) {
+        if surface_to_view.cos_angle_between(frame.real_normal) >= 0f32 {
+            (frame.normal, self.ior)
+        } else {
+            (-frame.normal, 1f32 / self.ior)
+        }
+    }
+
+    /// The microfacet normal, on the same side as `n`, that scatters `v`
+    /// into `l`, or `None` for configurations no microfacet produces.
+    fn half_vector(v: Direction, l: Direction, n: Direction, eta: f32) -> Option<Direction> {
+        let reflected = n.cos_angle_between(l) > 0f32;
+        let h = if reflected {
+            v.vec + l.vec
+        } else {
+            v.vec + l.vec * eta
+        };
+        if h.length_squared() == 0f32 {
+            return None;
+        }
+        let mut h = Direction { vec: h.normalize() };
+        if h.cos_angle_between(n) < 0f32 {
+            h = -h;
+        }
+        // Both directions must lie on the microfacet's front side for a
+        // reflection and on opposite sides for a refraction.
+        let cos_v_h = h.cos_angle_between(v);
+        let cos_l_h = h.cos_angle_between(l);
+        if cos_v_h <= 0f32 || (cos_l_h > 0f32) != reflected {
+            return None;
+        }
+        Some(h)
+    }
+}
+
+impl Bsdf for RoughDielectric {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        let (v, l) = (surface_to_view, surface_to_light);
+        let (n, eta) = self.orient(v, frame);
+        let cos_n_v = n.cos_angle_between(v);
+        let cos_n_l = n.cos_angle_between(l);
+        let Some(h) = Self::half_vector(v, l, n, eta) else {
+            return HdrColor::new(0f32, 0f32, 0f32);
+        };
+        if cos_n_v <= 0f32 || cos_n_l == 0f32 {
+            return HdrColor::new(0f32, 0f32, 0f32);
+        }
+
+        let d = ggx_ndf(n, h, self.roughness);
+        let g = smith_g1_ggx(n, v, self.roughness) * smith_g1_ggx(n, l, self.roughness);
+        let cos_v_h = h.cos_angle_between(v);
+        let f = fresnel_dielectric(cos_v_h, eta);
+        if cos_n_l > 0f32 {
+            let reflected = d * g * f / (4f32 * cos_n_v);
+            return HdrColor::new(reflected, reflected, reflected);
+        }
+
+        let cos_l_h = h.cos_angle_between(l);
+        let denom = cos_l_h * eta + cos_v_h;
+        let transmitted = d * g * (1f32 - f) * cos_l_h.abs() * cos_v_h / (denom * denom * cos_n_v);
+        self.transmittance * transmitted
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        let (n, eta) = self.orient(surface_to_view, frame);
+        let h = sample_ggx_ndf(n, self.roughness, u);
+        let cos_v_h = h.cos_angle_between(surface_to_view);
+        if cos_v_h <= 0f32 {
+            return None;
+        }
+        let f = fresnel_dielectric(cos_v_h, eta);
+        let reflected = u_lobe < f;
+        let surface_to_light = if reflected {
+            reflect(surface_to_view, h)
+        } else {
+            refract(surface_to_view, h, eta)?
+        };
+        // A reflection below the surface or a refraction above it is
+        // shadowed, and `pdf` would mistake it for the other lobe.
+        if (n.cos_angle_between(surface_to_light) > 0f32) != reflected {
+            return None;
+        }
+        let pdf = self.pdf(surface_to_view, surface_to_light, frame);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            surface_to_light,
+            value: self.evaluate(surface_to_view, surface_to_light, frame),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        let (v, l) = (surface_to_view, surface_to_light);
+        let (n, eta) = self.orient(v, frame);
+        let Some(h) = Self::half_vector(v, l, n, eta) else {
+            return 0f32;
+        };
+        let pdf_h = ggx_ndf(n, h, self.roughness) * h.cos_angle_between(n);
+        let cos_v_h = h.cos_angle_between(v);
+        let f = fresnel_dielectric(cos_v_h, eta);
+        if n.cos_angle_between(l) > 0f32 {
+            f * pdf_h / (4f32 * cos_v_h)
+        } else {
+            let cos_l_h = h.cos_angle_between(l);
+            let denom = cos_l_h * eta + cos_v_h;
+            (1f32 - f) * pdf_h * cos_l_h.abs() * eta * eta / (denom * denom)
+        }
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        let (n, eta) = self.orient(surface_to_view, frame);
+        let f = fresnel_dielectric(surface_to_view.cos_angle_between(n), eta);
+        HdrColor::new(f, f, f)
+    }
+
+    fn transmits(&self) -> bool {
+        true
+    }
+}
+
+/// A smooth or glossy transparent varnish with index of refraction 1.5 over
+/// `base`, which only receives the light the coat does not reflect.
+#[derive(Clone, Copy)]
+pub struct ClearCoat<B: Bsdf> {
+    pub base: B,
+    pub roughness: f32,
+    /// How much of the surface is covered, from 0 to 1.
+    pub weight: f32,
+}
+
+impl<B: Bsdf> ClearCoat<B> {
+    /// Fraction of light passing through the coat along `direction`.
+    fn transmission(&self, direction: Direction, normal: Direction) -> f32 {
+        let cos_theta = normal.cos_angle_between(direction).abs();
+        1f32 - self.weight * fresnel_schlick(cos_theta, F0_NORMAL).g
+    }
+
+    fn coat(&self, v: Direction, l: Direction, n: Direction) -> f32 {
+        let cos_n_v = n.cos_angle_between(v);
+        let cos_n_l = n.cos_angle_between(l);
+        if cos_n_v <= 0f32 || cos_n_l <= 0f32 {
+            return 0f32;
+        }
+        let h = Direction::from_directions([v, l]);
+        let d = ggx_ndf(n, h, self.roughness);
+        let f = fresnel_schlick(h.cos_angle_between(v), F0_NORMAL).g;
+        let g = smith_g1_ggx(n, v, self.roughness) * smith_g1_ggx(n, l, self.roughness);
+        self.weight * d * f * g / (4f32 * cos_n_v)
+    }
+
+    fn coat_pdf(&self, v: Direction, l: Direction, n: Direction) -> f32 {
+        if n.cos_angle_between(l) <= 0f32 || n.cos_angle_between(v) <= 0f32 {
+            return 0f32;
+        }
+        let h = Direction::from_directions([v, l]);
+        ggx_ndf(n, h, self.roughness) * n.cos_angle_between(h)
+            / (4f32 * h.cos_angle_between(v).abs())
+    }
+
+    /// Probability of sampling the coat rather than the base.
+    fn coat_probability(&self, surface_to_view: Direction, frame: ShadingFrame) -> f32 {
+        let coat = 1f32 - self.transmission(surface_to_view, frame.normal);
+        let base = self.base.reflectance(surface_to_view, frame).luminance() * (1f32 - coat);
+        if coat + base > 0f32 {
+            coat / (coat + base)
+        } else {
+            0.5f32
+        }
+    }
+}
+
+impl<B: Bsdf> Bsdf for ClearCoat<B> {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        let (v, l, n) = (surface_to_view, surface_to_light, frame.normal);
+        let coat = self.coat(v, l, n);
+        self.base.evaluate(v, l, frame) * (self.transmission(v, n) * self.transmission(l, n))
+            + HdrColor::new(coat, coat, coat)
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        let coat_probability = self.coat_probability(surface_to_view, frame);
+        let surface_to_light = if u_lobe < coat_probability {
+            let h = sample_ggx_ndf(frame.normal, self.roughness, u);
+            reflect(surface_to_view, h)
+        } else {
+            let u_lobe = (u_lobe - coat_probability) / (1f32 - coat_probability);
+            let sample = self.base.sample(surface_to_view, frame, u_lobe, u)?;
+            if sample.specular {
+                let attenuation = self.transmission(surface_to_view, frame.normal)
+                    * self.transmission(sample.surface_to_light, frame.normal);
+                return Some(BsdfSample {
+                    value: sample.value * (attenuation * (1f32 - coat_probability)),
+                    pdf: sample.pdf * (1f32 - coat_probability),
+                    ..sample
+                });
+            }
+            sample.surface_to_light
+        };
+        let pdf = self.pdf(surface_to_view, surface_to_light, frame);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            surface_to_light,
+            value: self.evaluate(surface_to_view, surface_to_light, frame),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        let coat_probability = self.coat_probability(surface_to_view, frame);
+        coat_probability * self.coat_pdf(surface_to_view, surface_to_light, frame.normal)
+            + (1f32 - coat_probability) * self.base.pdf(surface_to_view, surface_to_light, frame)
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        let transmission = self.transmission(surface_to_view, frame.normal);
+        let coat = 1f32 - transmission;
+        self.base.reflectance(surface_to_view, frame) * (transmission * transmission)
+            + HdrColor::new(coat, coat, coat)
+    }
+
+    fn transmits(&self) -> bool {
+        self.base.transmits()
+    }
+
+    fn emission(&self) -> HdrColor {
+        self.base.emission()
+    }
+
+    fn specular_lobes(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        let transmission = self.transmission(surface_to_view, frame.normal);
+        self.base
+            .specular_lobes(surface_to_view, frame)
+            .into_iter()
+            .map(|(direction, weight)| {
+                (
+                    direction,
+                    weight * (transmission * self.transmission(direction, frame.normal)),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Blends two materials, as if a `weight` fraction of the surface were
+/// covered by `b` and the rest by `a`.
+#[derive(Clone, Copy)]
+pub struct Mix<A: Bsdf, B: Bsdf> {
+    pub a: A,
+    pub b: B,
+    pub weight: f32,
+}
+
+impl<A: Bsdf, B: Bsdf> Bsdf for Mix<A, B> {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        self.a.evaluate(surface_to_view, surface_to_light, frame) * (1f32 - self.weight)
+            + self.b.evaluate(surface_to_view, surface_to_light, frame) * self.weight
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        let sample = if u_lobe < self.weight {
+            self.b
+                .sample(surface_to_view, frame, u_lobe / self.weight, u)
+        } else {
+            let u_lobe = (u_lobe - self.weight) / (1f32 - self.weight);
+            self.a.sample(surface_to_view, frame, u_lobe, u)
+        }?;
+        if sample.specular {
+            let probability = if u_lobe < self.weight {
+                self.weight
+            } else {
+                1f32 - self.weight
+            };
+            return Some(BsdfSample {
+                value: sample.value * probability,
+                pdf: sample.pdf * probability,
+                ..sample
+            });
+        }
+        let pdf = self.pdf(surface_to_view, sample.surface_to_light, frame);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            value: self.evaluate(surface_to_view, sample.surface_to_light, frame),
+            pdf,
+            ..sample
+        })
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        self.a.pdf(surface_to_view, surface_to_light, frame) * (1f32 - self.weight)
+            + self.b.pdf(surface_to_view, surface_to_light, frame) * self.weight
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        self.a.reflectance(surface_to_view, frame) * (1f32 - self.weight)
+            + self.b.reflectance(surface_to_view, frame) * self.weight
+    }
+
+    fn transmits(&self) -> bool {
+        (self.weight < 1f32 && self.a.transmits()) || (self.weight > 0f32 && self.b.transmits())
+    }
+
+    fn specular_lobes(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        let a = self.a.specular_lobes(surface_to_view, frame).into_iter();
+        let b = self.b.specular_lobes(surface_to_view, frame).into_iter();
+        a.map(|(direction, weight)| (direction, weight * (1f32 - self.weight)))
+            .chain(b.map(|(direction, weight)| (direction, weight * self.weight)))
+            .collect()
+    }
+
+    fn emission(&self) -> HdrColor {
+        self.a.emission() * (1f32 - self.weight) + self.b.emission() * self.weight
+    }
+}
+
+/// `base` glowing with `emission`.
+#[derive(Clone, Copy)]
+pub struct Emissive<B: Bsdf> {
+    pub base: B,
+    pub emission: HdrColor,
+}
+
+impl<B: Bsdf> Bsdf for Emissive<B> {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        self.base.evaluate(surface_to_view, surface_to_light, frame)
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        self.base.sample(surface_to_view, frame, u_lobe, u)
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        self.base.pdf(surface_to_view, surface_to_light, frame)
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        self.base.reflectance(surface_to_view, frame)
+    }
+
+    fn transmits(&self) -> bool {
+        self.base.transmits()
+    }
+
+    fn specular_lobes(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        self.base.specular_lobes(surface_to_view, frame)
+    }
+
+    fn emission(&self) -> HdrColor {
+        self.base.emission() + self.emission
+    }
+
+    fn subsurface(&self) -> Option<SubsurfaceMedium> {
+        self.base.subsurface()
+    }
+}
+
+const SHEEN_ALBEDO_STEPS: usize = 16;
+
+/// Estevez and Kulla's "Charlie" microfiber distribution.
+fn charlie_ndf(cos_theta_h: f32, alpha: f32) -> f32 {
+    let sin_theta_h = (1f32 - cos_theta_h * cos_theta_h).max(0f32).sqrt();
+    let inverse_alpha = 1f32 / alpha;
+    (2f32 + inverse_alpha) * sin_theta_h.powf(inverse_alpha) / (2f32 * std::f32::consts::PI)
+}
+
+/// Ashikhmin's visibility term, as used with the Charlie distribution.
+fn sheen_visibility(cos_n_v: f32, cos_n_l: f32) -> f32 {
+    1f32 / (4f32 * (cos_n_l + cos_n_v - cos_n_l * cos_n_v))
+}
+
+/// A retroreflective fuzz of fibres over `base`, as found on cloth and velvet.
+#[derive(Clone)]
+pub struct Sheen<B: Bsdf> {
+    base: B,
+    color: LdrColor,
+    alpha: f32,
+    /// Directional albedo of a white sheen by cosine of the view angle.
+    albedo: [f32; SHEEN_ALBEDO_STEPS],
+}
+
+impl<B: Bsdf> Sheen<B> {
+    pub fn new(base: B, color: LdrColor, roughness: f32) -> Sheen<B> {
+        const THETA_STEPS: usize = 32;
+        const PHI_STEPS: usize = 64;
+        let alpha = (roughness * roughness).max(0.001f32);
+        let mut albedo = [0f32; SHEEN_ALBEDO_STEPS];
+        for (i, albedo) in albedo.iter_mut().enumerate() {
+            let cos_n_v = (i as f32 + 0.5f32) / SHEEN_ALBEDO_STEPS as f32;
+            let v = Vec3((1f32 - cos_n_v * cos_n_v).sqrt(), 0f32, cos_n_v);
+            let d_theta = std::f32::consts::FRAC_PI_2 / THETA_STEPS as f32;
+            let d_phi = 2f32 * std::f32::consts::PI / PHI_STEPS as f32;
+            for j in 0..THETA_STEPS {
+                let theta = (j as f32 + 0.5f32) * d_theta;
+                let cos_n_l = theta.cos();
+                for k in 0..PHI_STEPS {
+                    let phi = (k as f32 + 0.5f32) * d_phi;
+                    let l = Vec3(theta.sin() * phi.cos(), theta.sin() * phi.sin(), cos_n_l);
+                    let h = (v + l).normalize();
+                    *albedo += charlie_ndf(h.2, alpha)
+                        * sheen_visibility(cos_n_v, cos_n_l)
+                        * cos_n_l
+                        * theta.sin()
+                        * d_theta
+                        * d_phi;
+                }
+            }
+        }
+        Sheen {
+            base,
+            color,
+            alpha,
+            albedo,
+        }
+    }
+
+    /// Albedo of a white sheen seen from `surface_to_view`.
+    fn white_albedo(&self, surface_to_view: Direction, normal: Direction) -> f32 {
+        let cos_n_v = normal.cos_angle_between(surface_to_view).clamp(0f32, 1f32);
+        let index = ((cos_n_v * SHEEN_ALBEDO_STEPS as f32) as usize).min(SHEEN_ALBEDO_STEPS - 1);
+        self.albedo[index]
+    }
+
+    /// Fraction of light scattered by the sheen rather than reaching the base.
+    fn sheen_albedo(&self, surface_to_view: Direction, normal: Direction) -> f32 {
+        let max_color = self.color.r.max(self.color.g).max(self.color.b);
+        max_color * self.white_albedo(surface_to_view, normal)
+    }
+
+    fn sheen_probability(&self, surface_to_view: Direction, frame: ShadingFrame) -> f32 {
+        let sheen = self.sheen_albedo(surface_to_view, frame.normal);
+        let base = self.base.reflectance(surface_to_view, frame).luminance() * (1f32 - sheen);
+        if sheen + base > 0f32 {
+            sheen / (sheen + base)
+        } else {
+            0.5f32
+        }
+    }
+}
+
+impl<B: Bsdf> Bsdf for Sheen<B> {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        let (v, l, n) = (surface_to_view, surface_to_light, frame.normal);
+        let base = self.base.evaluate(v, l, frame) * (1f32 - self.sheen_albedo(v, n));
+        let cos_n_v = n.cos_angle_between(v);
+        let cos_n_l = n.cos_angle_between(l);
+        if cos_n_v <= 0f32 || cos_n_l <= 0f32 {
+            return base;
+        }
+        let h = Direction::from_directions([v, l]);
+        let sheen = charlie_ndf(n.cos_angle_between(h), self.alpha)
+            * sheen_visibility(cos_n_v, cos_n_l)
+            * cos_n_l;
+        base + HdrColor::from(self.color) * sheen
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        // The sheen is broad enough that cosine sampling serves it well.
+        let sheen_probability = self.sheen_probability(surface_to_view, frame);
+        let surface_to_light = if u_lobe < sheen_probability {
+            sample_cosine_hemisphere(frame.normal, u)
+        } else {
+            let u_lobe = (u_lobe - sheen_probability) / (1f32 - sheen_probability);
+            let sample = self.base.sample(surface_to_view, frame, u_lobe, u)?;
+            if sample.specular {
+                let attenuation = 1f32 - self.sheen_albedo(surface_to_view, frame.normal);
+                return Some(BsdfSample {
+                    value: sample.value * (attenuation * (1f32 - sheen_probability)),
+                    pdf: sample.pdf * (1f32 - sheen_probability),
+                    ..sample
+                });
+            }
+            sample.surface_to_light
+        };
+        let pdf = self.pdf(surface_to_view, surface_to_light, frame);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            surface_to_light,
+            value: self.evaluate(surface_to_view, surface_to_light, frame),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        let sheen_probability = self.sheen_probability(surface_to_view, frame);
+        let cos_n_l = frame.normal.cos_angle_between(surface_to_light);
+        let sheen_pdf = if cos_n_l > 0f32 {
+            cos_n_l / std::f32::consts::PI
+        } else {
+            0f32
+        };
+        sheen_probability * sheen_pdf
+            + (1f32 - sheen_probability) * self.base.pdf(surface_to_view, surface_to_light, frame)
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        let sheen = self.sheen_albedo(surface_to_view, frame.normal);
+        self.base.reflectance(surface_to_view, frame) * (1f32 - sheen)
+            + HdrColor::from(self.color) * self.white_albedo(surface_to_view, frame.normal)
+    }
+
+    fn transmits(&self) -> bool {
+        self.base.transmits()
+    }
+
+    fn emission(&self) -> HdrColor {
+        self.base.emission()
+    }
+
+    fn specular_lobes(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        let attenuation = 1f32 - self.sheen_albedo(surface_to_view, frame.normal);
+        self.base
+            .specular_lobes(surface_to_view, frame)
+            .into_iter()
+            .map(|(direction, weight)| (direction, weight * attenuation))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SubsurfaceMedium {
+    /// Average distance light travels between scattering events.
+    pub mean_free_path: HdrColor,
+    /// Fraction of light surviving each scattering event.
+    pub single_scattering_albedo: HdrColor,
+    pub ior: f32,
+}
+
+/// Skin, wax or marble: a glossy dielectric surface over a scattering
+/// medium whose colour is `color` once light has bounced around inside.
+#[derive(Clone, Copy)]
+pub struct Subsurface {
+    pub color: LdrColor,
+    pub mean_free_path: HdrColor,
+    pub ior: f32,
+    pub roughness: f32,
+}
+
+impl Subsurface {
+    fn surface(&self) -> CookTorrance {
+        let f0 = ((self.ior - 1f32) / (self.ior + 1f32)).powi(2);
+        CookTorrance {
+            albedo: LdrColor::new(0f32, 0f32, 0f32),
+            roughness_u: self.roughness,
+            roughness_v: self.roughness,
+            f0: LdrColor::new(f0, f0, f0),
+            thin_film: None,
+        }
+    }
+}
+
+impl Bsdf for Subsurface {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        self.surface()
+            .evaluate(surface_to_view, surface_to_light, frame)
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        self.surface().sample(surface_to_view, frame, u_lobe, u)
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        self.surface().pdf(surface_to_view, surface_to_light, frame)
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        self.surface().reflectance(surface_to_view, frame)
+    }
+
+    fn subsurface(&self) -> Option<SubsurfaceMedium> {
+        // Chiang et al., "Practical and Controllable Subsurface Scattering
+        // for Production Path Tracing", inverting the multiple-scattering
+        // albedo of a random walk.
+        let invert = |a: f32| {
+            let s = 4.09712f32 + 4.20863f32 * a
+                - (9.59217f32 + 41.6808f32 * a + 17.7126f32 * a * a).sqrt();
+            1f32 - s * s
+        };
+        Some(SubsurfaceMedium {
+            mean_free_path: self.mean_free_path,
+            single_scattering_albedo: HdrColor::new(
+                invert(self.color.r),
+                invert(self.color.g),
+                invert(self.color.b),
+            ),
+            ior: self.ior,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    fn hemisphere_integral(f: impl Fn(Direction) -> f32) -> f32 {
+        let steps = 256;
+        let d_theta = std::f32::consts::FRAC_PI_2 / steps as f32;
+        let d_phi = 2f32 * std::f32::consts::PI / steps as f32;
+        let mut integral = 0f32;
+        for i in 0..steps {
+            let theta = (i as f32 + 0.5f32) * d_theta;
+            for j in 0..steps {
+                let phi = (j as f32 + 0.5f32) * d_phi;
+                let direction = Direction {
+                    vec: Vec3(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    ),
+                };
+                integral += f(direction) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        integral
+    }
+
+    fn up() -> ShadingFrame {
+        let normal = Direction {
+            vec: Vec3(0f32, 0f32, 1f32),
+        };
+        ShadingFrame::new(
+            normal,
+            normal,
+            Direction {
+                vec: Vec3(1f32, 0f32, 0f32),
+            },
+        )
+    }
+
+    fn view(cos_theta: f32) -> Direction {
+        Direction {
+            vec: Vec3((1f32 - cos_theta * cos_theta).sqrt(), 0f32, cos_theta),
+        }
+    }
+
+    fn sphere_integral(f: impl Fn(Direction) -> f32) -> f32 {
+        let flipped = |direction: Direction| {
+            f(Direction {
+                vec: Vec3(direction.vec.0, direction.vec.1, -direction.vec.2),
+            })
+        };
+        hemisphere_integral(&f) + hemisphere_integral(flipped)
+    }
+
+    /// Checks that `sample` draws directions with the density it reports,
+    /// as then averaging `value / pdf` over many samples estimates the
+    /// same integral of `evaluate` that quadrature finds. Specular samples
+    /// are left out, as `evaluate` cannot describe them.
+    pub(crate) fn assert_samples_follow_pdf(bsdf: &dyn Bsdf, surface_to_view: Direction) {
+        let frame = up();
+        let expected = sphere_integral(|light| bsdf.evaluate(surface_to_view, light, frame).g);
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let samples = 42000;
+        let estimate = (0..samples)
+            .filter_map(|_| {
+                let sample =
+                    bsdf.sample(surface_to_view, frame, rng.gen(), (rng.gen(), rng.gen()))?;
+                (!sample.specular).then(|| sample.value.g / sample.pdf)
+            })
+            .sum::<f32>()
+            / samples as f32;
+        assert!(
+            (estimate - expected).abs() < 0.03f32 * expected.max(0.1f32),
+            "sampled {estimate}, integrated {expected}"
+        );
+    }
+
+    #[test]
+    fn test_cook_torrance_conserves_energy() {
+        for &roughness in &[0.3f32, 0.6f32, 1f32] {
+            let bsdf = CookTorrance {
+                albedo: LdrColor::new(1f32, 1f32, 1f32),
+                roughness_u: roughness,
+                roughness_v: roughness,
+                f0: F0_NORMAL,
+                thin_film: None,
+            };
+            for &cos_theta in &[0.2f32, 0.6f32, 1f32] {
+                let albedo =
+                    hemisphere_integral(|light| bsdf.evaluate(view(cos_theta), light, up()).g);
+                assert!(albedo <= 1.05f32);
+                assert!(albedo > 0.7f32);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cook_torrance_pdf_is_normalized() {
+        for &roughness in &[0.3f32, 0.6f32, 1f32] {
+            let bsdf = CookTorrance {
+                albedo: LdrColor::new(0.5f32, 0.5f32, 0.5f32),
+                roughness_u: roughness,
+                roughness_v: roughness,
+                f0: F0_GOLD,
+                thin_film: None,
+            };
+            for &cos_theta in &[0.4f32, 1f32] {
+                let total = hemisphere_integral(|light| bsdf.pdf(view(cos_theta), light, up()));
+                // Specular samples reflected below the horizon are lost.
+                assert!(total <= 1.01f32);
+                assert!(total > 0.5f32);
+                assert_samples_follow_pdf(&bsdf, view(cos_theta));
+            }
+        }
+    }
+
+    #[test]
+    fn test_anisotropic_ggx_is_normalized() {
+        for &(roughness_u, roughness_v) in &[(0.6f32, 0.4f32), (1f32, 0.5f32), (0.4f32, 0.8f32)] {
+            // The projected microfacet area always adds up to the surface's.
+            let area = hemisphere_integral(|h| {
+                ggx_ndf_anisotropic(up(), h, roughness_u, roughness_v) * h.vec.2
+            });
+            assert!((area - 1f32).abs() < 0.01f32);
+
+            let bsdf = CookTorrance {
+                albedo: LdrColor::new(0.5f32, 0.5f32, 0.5f32),
+                roughness_u,
+                roughness_v,
+                f0: F0_GOLD,
+                thin_film: None,
+            };
+            assert_samples_follow_pdf(&bsdf, view(0.6f32));
+        }
+    }
+
+    #[test]
+    fn test_dielectric_lobes() {
+        let glass = Dielectric {
+            ior: 1.5f32,
+            transmittance: LdrColor::new(1f32, 1f32, 1f32),
+        };
+
+        let lobes = glass.specular_lobes(view(1f32), up());
+        assert!((lobes[0].1.g - 0.04f32).abs() < 0.00042f32);
+        assert!((lobes[1].1.g * 1.5f32 * 1.5f32 - 0.96f32).abs() < 0.00042f32);
+        assert!(lobes[1].0.vec.2 < -0.99f32);
+
+        let outward = glass.specular_lobes(view(-0.2f32), up());
+        assert_eq!(outward.len(), 1);
+        assert!((outward[0].1.g - 1f32).abs() < 0.00042f32);
+        assert!(outward[0].0.vec.2 < 0f32);
+
+        for &cos_theta in &[0.1f32, 0.5f32, 0.9f32] {
+            let lobes = glass.specular_lobes(view(cos_theta), up());
+            let refracted = lobes[1].0;
+            let back = glass.specular_lobes(refracted, up());
+            assert!((back[1].0.cos_angle_between(view(cos_theta)) - 1f32).abs() < 0.00042f32);
+        }
+    }
+
+    #[test]
+    fn test_rough_dielectric_is_consistent() {
+        for &roughness in &[0.3f32, 0.6f32] {
+            let glass = RoughDielectric {
+                ior: 1.5f32,
+                roughness,
+                transmittance: LdrColor::new(1f32, 1f32, 1f32),
+            };
+            for &cos_theta in &[0.5f32, 1f32, -0.7f32] {
+                let v = view(cos_theta);
+                let total = sphere_integral(|light| glass.pdf(v, light, up()));
+                assert!(total <= 1.01f32);
+                assert!(total > 0.5f32);
+
+                // Transmitted radiance is scaled by the squared relative index
+                // of refraction, which cancels out for the energy balance.
+                let eta2 = if cos_theta > 0f32 {
+                    2.25f32
+                } else {
+                    1f32 / 2.25f32
+                };
+                let energy = sphere_integral(|light| {
+                    let value = glass.evaluate(v, light, up()).g;
+                    if light.vec.2 * cos_theta < 0f32 {
+                        value * eta2
+                    } else {
+                        value
+                    }
+                });
+                assert!(energy <= 1.05f32);
+                assert!(energy > 0.6f32);
+                assert_samples_follow_pdf(&glass, v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_clear_coat_is_consistent() {
+        for &roughness in &[0.1f32, 0.5f32] {
+            let coated = ClearCoat {
+                base: CookTorrance {
+                    albedo: LdrColor::new(1f32, 1f32, 1f32),
+                    roughness_u: 0.6f32,
+                    roughness_v: 0.6f32,
+                    f0: F0_NORMAL,
+                    thin_film: None,
+                },
+                roughness,
+                weight: 1f32,
+            };
+            for &cos_theta in &[0.3f32, 0.7f32, 1f32] {
+                let v = view(cos_theta);
+                let energy = hemisphere_integral(|light| coated.evaluate(v, light, up()).g);
+                assert!(energy <= 1.05f32);
+                assert!(energy > 0.7f32);
+                // The quadrature cannot resolve the glossier coat's peak.
+                if roughness >= 0.5f32 {
+                    assert_samples_follow_pdf(&coated, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sheen_is_consistent() {
+        for &roughness in &[0.3f32, 0.8f32] {
+            let sheen = Sheen::new(
+                CookTorrance {
+                    albedo: LdrColor::new(1f32, 1f32, 1f32),
+                    roughness_u: 0.6f32,
+                    roughness_v: 0.6f32,
+                    f0: F0_NORMAL,
+                    thin_film: None,
+                },
+                LdrColor::new(1f32, 1f32, 1f32),
+                roughness,
+            );
+            for &cos_theta in &[0.3f32, 0.7f32, 1f32] {
+                let v = view(cos_theta);
+                let energy = hemisphere_integral(|light| sheen.evaluate(v, light, up()).g);
+                assert!(energy <= 1.05f32);
+                assert!(energy > 0.7f32);
+                let expected = sheen.reflectance(v, up()).g;
+                assert!((energy - expected).abs() < 0.15f32);
+                assert_samples_follow_pdf(&sheen, v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_thin_film_vanishes_without_thickness() {
+        let film = ThinFilm {
+            thickness: 0f32,
+            ior: 1.33f32,
+        };
+        for &cos_theta in &[0.2f32, 0.6f32, 1f32] {
+            let expected = fresnel_dielectric(cos_theta, 1.5f32);
+            let actual = fresnel_thin_film(cos_theta, F0_NORMAL, film);
+            assert!((actual.g - expected).abs() < 0.001f32);
+        }
+
+        let film = ThinFilm {
+            thickness: 300f32,
+            ior: 1.33f32,
+        };
+        let f = fresnel_thin_film(1f32, F0_NORMAL, film);
+        assert!(f.r.max(f.g).max(f.b) - f.r.min(f.g).min(f.b) > 0.01f32);
+        assert!(f.r.max(f.g).max(f.b) <= 1f32);
+    }
+}
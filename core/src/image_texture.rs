@@ -0,0 +1,601 @@
+use std::{
+    io::{Error, ErrorKind, Read},
+    path::Path,
+};
+
+use crate::{
+    base_types::{HdrColor, LdrColor},
+    texture::{Texture, TextureContext},
+};
+
+/// How the 8 bit values of an image file relate to linear intensities.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    /// Colour maps, which are almost always stored gamma encoded.
+    Srgb,
+    /// Data like roughness or metalness, stored as is.
+    Linear,
+}
+
+/// What lies beyond the edges of an image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /// Bilinear lookups in the two mip levels closest to the footprint.
+    Trilinear,
+    /// Heckbert's elliptical weighted average over the footprint, which
+    /// stays sharp where surfaces are seen at grazing angles.
+    Ewa,
+}
+
+/// Footprints are widened to at most this ratio of their axes, bounding the
+/// number of texels an EWA lookup visits.
+const MAX_ANISOTROPY: f32 = 16f32;
+
+/// Linear colours in rows from the top of the image down.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<HdrColor>,
+}
+
+impl Image {
+    /// Reads a BMP, PPM or PFM file, chosen by its extension.
+    pub fn load(path: impl AsRef<Path>, color_space: ColorSpace) -> Result<Image, Error> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "bmp" => {
+                let image = bmp::open(path)
+                    .map_err(|error| Error::new(ErrorKind::InvalidData, error.to_string()))?;
+                let (width, height) = (image.get_width(), image.get_height());
+                if width == 0 || height == 0 {
+                    return Err(Error::new(ErrorKind::InvalidData, "empty BMP image"));
+                }
+                let mut pixels = Vec::with_capacity((width * height) as usize);
+                for y in 0..height {
+                    for x in 0..width {
+                        let bmp::Pixel { r, g, b } = image.get_pixel(x, y);
+                        pixels.push(decode([r, g, b].map(|c| c as f32 / 255f32), color_space));
+                    }
+                }
+                Ok(Image {
+                    width: width as usize,
+                    height: height as usize,
+                    pixels,
+                })
+            }
+            "ppm" => Image::read_ppm(std::fs::File::open(path)?, color_space),
+            "pfm" => Image::read_pfm(std::fs::File::open(path)?),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("unsupported image format {:?}", extension),
+            )),
+        }
+    }
+
+    /// Reads a binary (P6) or plain (P3) portable pixmap.
+    pub fn read_ppm(mut reader: impl Read, color_space: ColorSpace) -> Result<Image, Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        /// Skips whitespace and comments, then returns the next word.
+        fn token<'a>(bytes: &'a [u8], cursor: &mut usize) -> Option<&'a str> {
+            loop {
+                while *cursor < bytes.len() && bytes[*cursor].is_ascii_whitespace() {
+                    *cursor += 1;
+                }
+                if *cursor < bytes.len() && bytes[*cursor] == b'#' {
+                    while *cursor < bytes.len() && bytes[*cursor] != b'\n' {
+                        *cursor += 1;
+                    }
+                } else {
+                    break;
+                }
+            }
+            let start = *cursor;
+            while *cursor < bytes.len() && !bytes[*cursor].is_ascii_whitespace() {
+                *cursor += 1;
+            }
+            std::str::from_utf8(&bytes[start..*cursor]).ok()
+        }
+        let number = |cursor: &mut usize| -> Result<usize, Error> {
+            token(&bytes, cursor)
+                .and_then(|token| token.parse().ok())
+                .ok_or_else(|| invalid("malformed PPM header"))
+        };
+
+        let mut cursor = 0;
+        let magic = token(&bytes, &mut cursor).ok_or_else(|| invalid("missing PPM magic"))?;
+        let (width, height, max_value) = (
+            number(&mut cursor)?,
+            number(&mut cursor)?,
+            number(&mut cursor)?,
+        );
+        if width == 0 || height == 0 {
+            return Err(invalid("empty PPM image"));
+        }
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid("unsupported PPM maximum value"));
+        }
+        let count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or_else(|| invalid("PPM size too large"))?;
+        let samples: Vec<usize> = match magic {
+            "P3" => (0..count)
+                .map(|_| number(&mut cursor))
+                .collect::<Result<_, _>>()?,
+            "P6" => {
+                // A single whitespace character separates the header from
+                // the samples.
+                let start = cursor + 1;
+                let size = if max_value < 256 { 1 } else { 2 };
+                let data = count
+                    .checked_mul(size)
+                    .and_then(|length| bytes.get(start..start.checked_add(length)?))
+                    .ok_or_else(|| invalid("truncated PPM data"))?;
+                if size == 1 {
+                    data.iter().map(|&sample| sample as usize).collect()
+                } else {
+                    data.chunks(2)
+                        .map(|sample| (sample[0] as usize) << 8 | sample[1] as usize)
+                        .collect()
+                }
+            }
+            _ => return Err(invalid("not a PPM file")),
+        };
+        let pixels = samples
+            .chunks(3)
+            .map(|rgb| {
+                decode(
+                    [rgb[0], rgb[1], rgb[2]].map(|c| c as f32 / max_value as f32),
+                    color_space,
+                )
+            })
+            .collect();
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Reads a portable float map, colour (PF) or greyscale (Pf), whose
+    /// samples are already linear.
+    pub fn read_pfm(mut reader: impl Read) -> Result<Image, Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        // The header is three lines: the magic, the size and the scale.
+        let mut lines = Vec::new();
+        let mut cursor = 0;
+        while lines.len() < 3 {
+            let end = bytes[cursor..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .ok_or_else(|| invalid("truncated PFM header"))?;
+            lines.push(
+                std::str::from_utf8(&bytes[cursor..cursor + end])
+                    .map_err(|_| invalid("malformed PFM header"))?
+                    .trim()
+                    .to_string(),
+            );
+            cursor += end + 1;
+        }
+        let channels = match lines[0].as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid("not a PFM file")),
+        };
+        let mut size = lines[1].split_whitespace().map(str::parse::<usize>);
+        let (width, height) = match (size.next(), size.next()) {
+            (Some(Ok(width)), Some(Ok(height))) => (width, height),
+            _ => return Err(invalid("malformed PFM size")),
+        };
+        if width == 0 || height == 0 {
+            return Err(invalid("empty PFM image"));
+        }
+        let scale: f32 = lines[2]
+            .parse()
+            .map_err(|_| invalid("malformed PFM scale"))?;
+
+        let length = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels * 4))
+            .ok_or_else(|| invalid("PFM size too large"))?;
+        let data = cursor
+            .checked_add(length)
+            .and_then(|end| bytes.get(cursor..end))
+            .ok_or_else(|| invalid("truncated PFM data"))?;
+        let samples: Vec<f32> = data
+            .chunks(4)
+            .map(|sample| {
+                let sample = [sample[0], sample[1], sample[2], sample[3]];
+                // A negative scale marks little-endian data.
+                if scale < 0f32 {
+                    f32::from_le_bytes(sample)
+                } else {
+                    f32::from_be_bytes(sample)
+                }
+            })
+            .collect();
+        // Rows are stored from the bottom of the image up.
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in samples.chunks(width * channels).rev() {
+            for pixel in row.chunks(channels) {
+                pixels.push(if channels == 3 {
+                    HdrColor::new(pixel[0], pixel[1], pixel[2])
+                } else {
+                    HdrColor::new(pixel[0], pixel[0], pixel[0])
+                });
+            }
+        }
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Averages blocks of two by two pixels, keeping the last row or column
+    /// of odd sized images.
+    fn downsample(&self) -> Image {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = HdrColor::new(0f32, 0f32, 0f32);
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let x = (x * 2 + dx).min(self.width - 1);
+                    let y = (y * 2 + dy).min(self.height - 1);
+                    sum = sum + self.pixels[y * self.width + x];
+                }
+                pixels.push(sum * 0.25f32);
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+fn decode(rgb: [f32; 3], color_space: ColorSpace) -> HdrColor {
+    let [r, g, b] = match color_space {
+        ColorSpace::Srgb => rgb.map(|c| {
+            if c <= 0.04045f32 {
+                c / 12.92f32
+            } else {
+                ((c + 0.055f32) / 1.055f32).powf(2.4f32)
+            }
+        }),
+        ColorSpace::Linear => rgb,
+    };
+    HdrColor::new(r, g, b)
+}
+
+/// An image mapped onto the unit square of UV space, with v pointing up the
+/// image. Scalar lookups read the red channel, as greyscale maps store the
+/// same value in all three.
+pub struct ImageTexture {
+    /// The mip map pyramid, from the full image down to a single pixel.
+    levels: Vec<Image>,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+impl ImageTexture {
+    /// Panics if the image has no pixels, as there is nothing to look up.
+    pub fn new(image: Image, wrap: WrapMode, filter: Filter) -> ImageTexture {
+        assert!(
+            image.width > 0 && image.height > 0 && image.pixels.len() == image.width * image.height,
+            "image texture needs a non-empty, complete image"
+        );
+        let mut levels = vec![image];
+        while let Some(last) = levels.last() {
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            levels.push(last.downsample());
+        }
+        ImageTexture {
+            levels,
+            wrap,
+            filter,
+        }
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> HdrColor {
+        let image = &self.levels[level];
+        let wrap = |i: i64, size: usize| {
+            let size = size as i64;
+            match self.wrap {
+                WrapMode::Repeat => i.rem_euclid(size),
+                WrapMode::Clamp => i.clamp(0, size - 1),
+                WrapMode::Mirror => {
+                    let i = i.rem_euclid(2 * size);
+                    if i < size {
+                        i
+                    } else {
+                        2 * size - 1 - i
+                    }
+                }
+            }
+        };
+        image.pixels[wrap(y, image.height) as usize * image.width + wrap(x, image.width) as usize]
+    }
+
+    fn nearest(&self, level: usize, (s, t): (f32, f32)) -> HdrColor {
+        let image = &self.levels[level];
+        self.texel(
+            level,
+            (s * image.width as f32).floor() as i64,
+            (t * image.height as f32).floor() as i64,
+        )
+    }
+
+    fn bilinear(&self, level: usize, (s, t): (f32, f32)) -> HdrColor {
+        let image = &self.levels[level];
+        let x = s * image.width as f32 - 0.5f32;
+        let y = t * image.height as f32 - 0.5f32;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        self.texel(level, x0, y0) * ((1f32 - dx) * (1f32 - dy))
+            + self.texel(level, x0 + 1, y0) * (dx * (1f32 - dy))
+            + self.texel(level, x0, y0 + 1) * ((1f32 - dx) * dy)
+            + self.texel(level, x0 + 1, y0 + 1) * (dx * dy)
+    }
+
+    /// Blends bilinear lookups in the levels around the fractional `level`.
+    fn trilinear(&self, level: f32, st: (f32, f32)) -> HdrColor {
+        let level = level.clamp(0f32, (self.levels.len() - 1) as f32);
+        let lower = level.floor() as usize;
+        if lower + 1 >= self.levels.len() {
+            return self.bilinear(lower, st);
+        }
+        let t = level - lower as f32;
+        self.bilinear(lower, st) * (1f32 - t) + self.bilinear(lower + 1, st) * t
+    }
+
+    /// Filters with a Gaussian over the ellipse spanned by `axes`, given in
+    /// units of the whole image.
+    fn ewa(&self, level: usize, st: (f32, f32), axes: [(f32, f32); 2]) -> HdrColor {
+        const ALPHA: f32 = 2f32;
+        if level >= self.levels.len() - 1 {
+            return self.texel(self.levels.len() - 1, 0, 0);
+        }
+        let image = &self.levels[level];
+        let (width, height) = (image.width as f32, image.height as f32);
+        // The centre in texels of this level.
+        let s = st.0 * width - 0.5f32;
+        let t = st.1 * height - 0.5f32;
+        let [(ds0, dt0), (ds1, dt1)] = axes.map(|(ds, dt)| (ds * width, dt * height));
+
+        // The implicit ellipse A s² + B s t + C t² = F, enlarged by a texel
+        // so that it always covers some.
+        let a = dt0 * dt0 + dt1 * dt1 + 1f32;
+        let b = -2f32 * (ds0 * dt0 + ds1 * dt1);
+        let c = ds0 * ds0 + ds1 * ds1 + 1f32;
+        let inverse_f = 1f32 / (a * c - b * b * 0.25f32);
+        let (a, b, c) = (a * inverse_f, b * inverse_f, c * inverse_f);
+
+        let determinant = -b * b + 4f32 * a * c;
+        let inverse_determinant = 1f32 / determinant;
+        let s_extent = 2f32 * inverse_determinant * (determinant * c).sqrt();
+        let t_extent = 2f32 * inverse_determinant * (determinant * a).sqrt();
+        let (s0, s1) = ((s - s_extent).ceil() as i64, (s + s_extent).floor() as i64);
+        let (t0, t1) = ((t - t_extent).ceil() as i64, (t + t_extent).floor() as i64);
+
+        let mut sum = HdrColor::new(0f32, 0f32, 0f32);
+        let mut total_weight = 0f32;
+        for y in t0..=t1 {
+            let tt = y as f32 - t;
+            for x in s0..=s1 {
+                let ss = x as f32 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1f32 {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum = sum + self.texel(level, x, y) * weight;
+                    total_weight += weight;
+                }
+            }
+        }
+        if total_weight > 0f32 {
+            sum * (1f32 / total_weight)
+        } else {
+            self.bilinear(level, st)
+        }
+    }
+
+    fn lookup(&self, context: &TextureContext) -> HdrColor {
+        let (u, v) = context.uv;
+        let st = (u, 1f32 - v);
+        // Footprint axes in units of the whole image.
+        let mut axes = [context.duv_dx, context.duv_dy].map(|(du, dv)| (du, -dv));
+        let base = &self.levels[0];
+        let resolution = base.width.max(base.height) as f32;
+        let length = |(ds, dt): (f32, f32)| (ds * ds + dt * dt).sqrt();
+        if !axes
+            .iter()
+            .all(|&(ds, dt)| ds.is_finite() && dt.is_finite())
+        {
+            // Rays nearly parallel to the surface cover all of it.
+            return self.bilinear(self.levels.len() - 1, st);
+        }
+
+        match self.filter {
+            Filter::Nearest => self.nearest(0, st),
+            Filter::Bilinear => self.bilinear(0, st),
+            Filter::Trilinear => {
+                let width = length(axes[0]).max(length(axes[1])) * resolution;
+                self.trilinear(width.max(1f32).log2(), st)
+            }
+            Filter::Ewa => {
+                if length(axes[0]) < length(axes[1]) {
+                    axes.swap(0, 1);
+                }
+                let major = length(axes[0]);
+                let mut minor = length(axes[1]);
+                if minor == 0f32 {
+                    return self.trilinear((major * resolution).max(1f32).log2(), st);
+                }
+                if minor * MAX_ANISOTROPY < major {
+                    let scale = major / (minor * MAX_ANISOTROPY);
+                    axes[1] = (axes[1].0 * scale, axes[1].1 * scale);
+                    minor = major / MAX_ANISOTROPY;
+                }
+                let level = (minor * resolution)
+                    .max(1f32)
+                    .log2()
+                    .min((self.levels.len() - 1) as f32);
+                let lower = level.floor() as usize;
+                let t = level - lower as f32;
+                if t == 0f32 {
+                    self.ewa(lower, st, axes)
+                } else {
+                    self.ewa(lower, st, axes) * (1f32 - t) + self.ewa(lower + 1, st, axes) * t
+                }
+            }
+        }
+    }
+}
+
+impl Texture<HdrColor> for ImageTexture {
+    fn evaluate(&self, context: &TextureContext) -> HdrColor {
+        self.lookup(context)
+    }
+}
+
+impl Texture<LdrColor> for ImageTexture {
+    fn evaluate(&self, context: &TextureContext) -> LdrColor {
+        let HdrColor { r, g, b } = self.lookup(context);
+        LdrColor::new(r, g, b)
+    }
+}
+
+impl Texture<f32> for ImageTexture {
+    fn evaluate(&self, context: &TextureContext) -> f32 {
+        self.lookup(context).r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_types::{Direction, Position},
+        math::Vec3,
+        EPSILON,
+    };
+
+    fn context(uv: (f32, f32), duv_dx: (f32, f32), duv_dy: (f32, f32)) -> TextureContext {
+        TextureContext {
+            position: Position::new(0f32, 0f32, 0f32),
+            uv,
+            duv_dx,
+            duv_dy,
+            normal: Direction {
+                vec: Vec3(0f32, 0f32, 1f32),
+            },
+        }
+    }
+
+    /// A 4 by 2 image whose left half is black and right half white.
+    fn halves() -> Image {
+        Image::read_ppm(
+            &b"P3\n# halves\n4 2\n255\n0 0 0 0 0 0 255 255 255 255 255 255\n\
+               0 0 0 0 0 0 255 255 255 255 255 255\n"[..],
+            ColorSpace::Linear,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_image_texture_filters() {
+        let value = |texture: &ImageTexture, uv, duv_dx, duv_dy| {
+            Texture::<f32>::evaluate(texture, &context(uv, duv_dx, duv_dy))
+        };
+        let texture = ImageTexture::new(halves(), WrapMode::Repeat, Filter::Bilinear);
+        assert_eq!(texture.levels.len(), 3);
+        let still = (0f32, 0f32);
+        assert!(value(&texture, (0.125f32, 0.5f32), still, still).abs() < EPSILON);
+        assert!((value(&texture, (0.5f32, 0.5f32), still, still) - 0.5f32).abs() < EPSILON);
+        // Repeating blends the last column with the first.
+        assert!((value(&texture, (1f32, 0.5f32), still, still) - 0.5f32).abs() < EPSILON);
+
+        for filter in [Filter::Trilinear, Filter::Ewa] {
+            let texture = ImageTexture::new(halves(), WrapMode::Clamp, filter);
+            // A footprint covering the whole image averages it.
+            let wide = value(&texture, (0.125f32, 0.5f32), (1f32, 0f32), (0f32, 1f32));
+            assert!((wide - 0.5f32).abs() < 0.01f32);
+            // A tiny one picks out the texel.
+            let narrow = value(
+                &texture,
+                (0.125f32, 0.5f32),
+                (0.001f32, 0f32),
+                (0f32, 0.001f32),
+            );
+            assert!(narrow.abs() < 0.01f32);
+        }
+    }
+
+    #[test]
+    fn test_read_pfm() {
+        let mut bytes = b"PF\n2 1\n-1.0\n".to_vec();
+        for value in [0.25f32, 0.5f32, 0.75f32, 2f32, 4f32, 8f32] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let image = Image::read_pfm(&bytes[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels[1].b, 8f32);
+        assert!(Image::read_pfm(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn test_read_rejects_overflowing_sizes() {
+        let huge = usize::MAX / 2;
+        let pfm = format!("PF\n{huge} 3\n-1.0\n");
+        assert!(Image::read_pfm(pfm.as_bytes()).is_err());
+        let ppm = format!("P6\n{huge} 3\n255\n");
+        assert!(Image::read_ppm(ppm.as_bytes(), ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn test_read_rejects_empty_images() {
+        let error = Image::read_pfm(&b"PF\n0 1\n-1\n"[..]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error = Image::read_ppm(&b"P3\n1 0\n255\n"[..], ColorSpace::Linear)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_image_texture_refuses_empty_images() {
+        let image = Image {
+            width: 0,
+            height: 1,
+            pixels: Vec::new(),
+        };
+        ImageTexture::new(image, WrapMode::Repeat, Filter::Bilinear);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    base_types::HdrColor,
+    bsdf::sample_cosine_hemisphere,
+    sampler::Sampler,
+    scene::{Ray, Scene},
+    ShadingPoint,
+};
+
+/// A strategy for estimating the radiance arriving along camera rays.
+pub trait Integrator {
+    /// `None` when `ray` leaves the scene into an environment that is not
+    /// drawn.
+    fn li(&self, scene: &Scene, ray: &Ray, sampler: &mut Sampler) -> Option<HdrColor>;
+}
+
+/// The fraction of the hemisphere above each hit left open by geometry
+/// closer than `distance`, in grey.
+pub struct AmbientOcclusion {
+    pub samples: usize,
+    pub distance: f32,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, scene: &Scene, ray: &Ray, sampler: &mut Sampler) -> Option<HdrColor> {
+        let white = HdrColor::new(1f32, 1f32, 1f32);
+        let Some(intersection) = scene.world.intersect(ray) else {
+            return scene.environment.background(ray.direction).map(|_| white);
+        };
+        let point = ShadingPoint::new(intersection, -ray.direction);
+        let open = (0..self.samples)
+            .filter(|_| {
+                let direction = sample_cosine_hemisphere(point.frame.normal, sampler.next_2d());
+                let origin = point.origin_towards(direction);
+                match scene.world.intersect(&Ray {
+                    origin,
+                    direction,
+                    differentials: None,
+                }) {
+                    Some(occluder) => (occluder.position - origin).distance() >= self.distance,
+                    None => true,
+                }
+            })
+            .count();
+        let open = open as f32 / self.samples.max(1) as f32;
+        Some(white * open)
+    }
+}
+
+/// Shows a property of the first hit instead of shading it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DebugView {
+    /// The shading normal, mapped from [-1, 1] to [0, 1].
+    Normals,
+    /// The fractional parts of the UV coordinates in red and green.
+    Uvs,
+}
+
+impl Integrator for DebugView {
+    fn li(&self, scene: &Scene, ray: &Ray, _sampler: &mut Sampler) -> Option<HdrColor> {
+        let Some(intersection) = scene.world.intersect(ray) else {
+            let black = HdrColor::new(0f32, 0f32, 0f32);
+            return scene.environment.background(ray.direction).map(|_| black);
+        };
+        Some(match self {
+            DebugView::Normals => {
+                let n = intersection.shading_normal().vec;
+                HdrColor::new(
+                    n.0 * 0.5f32 + 0.5f32,
+                    n.1 * 0.5f32 + 0.5f32,
+                    n.2 * 0.5f32 + 0.5f32,
+                )
+            }
+            DebugView::Uvs => {
+                let (u, v) = intersection.context.uv;
+                HdrColor::new(u - u.floor(), v - v.floor(), 0f32)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use crate::{
+        base_types::{LdrColor, Position, Transform},
+        bsdf::{CookTorrance, F0_NORMAL},
+        render,
+        scene::{Object, ObjectId, PerspectiveCamera, Plane},
+    };
+
+    #[test]
+    fn test_ambient_occlusion_and_debug_views() {
+        let plane = |id: u32, offset: f32, up: f32| {
+            Box::new(Plane::new(
+                ObjectId(id),
+                Transform::I,
+                offset,
+                0f32,
+                0f32,
+                up,
+                Rc::new(CookTorrance {
+                    albedo: LdrColor::new(0.5f32, 0.5f32, 0.5f32),
+                    roughness_u: 0.5f32,
+                    roughness_v: 0.5f32,
+                    f0: F0_NORMAL,
+                    thin_film: None,
+                }),
+            )) as Box<dyn Object>
+        };
+        let scene = |world: Vec<Box<dyn Object>>| {
+            Scene::new(
+                Box::new(PerspectiveCamera::by_x(
+                    Position::new(0f32, 0f32, 0f32),
+                    0f32,
+                    0f32,
+                    0f32,
+                    1f32,
+                    1f32,
+                )),
+                Box::new(world),
+                Box::new(HdrColor::new(1f32, 1f32, 1f32)),
+                vec![],
+            )
+        };
+        let floor = |scene: &Scene, integrator: &dyn Integrator| {
+            render(
+                scene,
+                integrator,
+                (0.5f32, 0.2f32),
+                (0.01f32, 0.01f32),
+                1,
+                &mut Sampler::new(42),
+            )
+            .unwrap()
+        };
+        let occlusion = AmbientOcclusion {
+            samples: 64,
+            distance: 1000f32,
+        };
+
+        let open = scene(vec![plane(0, 1f32, 1f32)]);
+        assert_eq!(floor(&open, &occlusion).g, 1f32);
+        let covered = scene(vec![plane(0, 1f32, 1f32), plane(1, 1f32, -1f32)]);
+        assert_eq!(floor(&covered, &occlusion).g, 0f32);
+
+        let normal = floor(&open, &DebugView::Normals);
+        assert!((normal.b - 1f32).abs() < 0.00042f32);
+        assert!(render(
+            &open,
+            &DebugView::Normals,
+            (0.5f32, 0.8f32),
+            (0.01f32, 0.01f32),
+            1,
+            &mut Sampler::new(42),
+        )
+        .is_none());
+    }
+}
@@ -1,102 +1,477 @@
-use base_types::{Direction, HdrColor, LdrColor};
-use scene::{Intersection, Material, Scene};
+use std::rc::Rc;
+
+use base_types::{Direction, HdrColor, LdrColor, Position};
+use bsdf::{
+    fresnel_dielectric, sample_cosine_hemisphere, sample_uniform_sphere, Bsdf, ShadingFrame,
+    SubsurfaceMedium,
+};
+use integrator::Integrator;
+use sampler::Sampler;
+use scene::{Intersection, Light, LightSample, LightSelection, ObjectId, Ray, Scene};
 
 pub mod base_types;
+pub mod bsdf;
+pub mod image_texture;
+pub mod integrator;
+pub mod light_sampling;
 pub mod math;
+pub mod measured;
+pub mod mesh;
+pub mod noise;
+pub mod path_tracer;
+pub mod principled;
+pub mod sampler;
 pub mod scene;
+pub mod sky;
+pub mod texture;
 
 pub const EPSILON: f32 = 0.00042f32;
 
-pub fn render(scene: &Scene, position_in_image: (f32, f32)) -> Option<HdrColor> {
-    let Intersection {
-        position,
-        real_normal,
-        adjusted_normal,
-        material,
-    } = scene
-        .world
-        .intersect(&scene.camera.get_ray(position_in_image))?;
-    let adjusted_position = position + real_normal * EPSILON;
-
-    let Material {
-        albedo,
-        roughness,
-        f0,
-    } = material;
-    Some(
-        scene
+/// How many specular bounces a camera ray may take unless the scene says
+/// otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// Specular bounces up to which every lobe is traced. Deeper ones follow a
+/// single lobe, so that the rays per camera ray grow linearly with depth.
+const SPLIT_SPECULAR_DEPTH: usize = 2;
+
+/// Random walks taken below a translucent surface per shading point.
+const SUBSURFACE_WALKS: usize = 8;
+
+/// Scattering events after which a random walk counts as absorbed.
+const MAX_SUBSURFACE_BOUNCES: usize = 256;
+
+/// The average of `samples_per_pixel` estimates by `integrator`, through
+/// the center of the pixel for a single sample or random points within it
+/// for more. `pixel_size` is the distance between neighbouring pixels in
+/// the same units as `position_in_image`, used to filter textures. `None`
+/// when every ray leaves into an environment that is not drawn.
+pub fn render<I: Integrator + ?Sized>(
+    scene: &Scene,
+    integrator: &I,
+    position_in_image: (f32, f32),
+    pixel_size: (f32, f32),
+    samples_per_pixel: usize,
+    sampler: &mut Sampler,
+) -> Option<HdrColor> {
+    let (x, y) = position_in_image;
+    let mut total = HdrColor::new(0f32, 0f32, 0f32);
+    let mut drawn = false;
+    for _ in 0..samples_per_pixel {
+        let (u, v) = if samples_per_pixel == 1 {
+            (0.5f32, 0.5f32)
+        } else {
+            sampler.next_2d()
+        };
+        let ray = scene.camera.get_ray_with_differentials(
+            (
+                x + (u - 0.5f32) * pixel_size.0,
+                y + (v - 0.5f32) * pixel_size.1,
+            ),
+            pixel_size,
+        );
+        if let Some(color) = integrator.li(scene, &ray, sampler) {
+            total = total + color;
+            drawn = true;
+        }
+    }
+    drawn.then(|| total * (1f32 / samples_per_pixel as f32))
+}
+
+/// Direct lighting plus mirror reflection and refraction, following
+/// specular lobes up to `Scene::max_depth` bounces.
+pub struct Whitted;
+
+impl Integrator for Whitted {
+    fn li(&self, scene: &Scene, ray: &Ray, sampler: &mut Sampler) -> Option<HdrColor> {
+        match scene.world.intersect(ray) {
+            Some(intersection) => Some(shade(scene, ray, intersection, sampler, 0)),
+            None => scene.environment.background(ray.direction),
+        }
+    }
+}
+
+fn trace(scene: &Scene, ray: &Ray, sampler: &mut Sampler, depth: usize) -> HdrColor {
+    match scene.world.intersect(ray) {
+        Some(intersection) => shade(scene, ray, intersection, sampler, depth),
+        None => scene.environment.radiance(ray.direction),
+    }
+}
+
+fn shade(
+    scene: &Scene,
+    ray: &Ray,
+    intersection: Intersection,
+    sampler: &mut Sampler,
+    depth: usize,
+) -> HdrColor {
+    let point = ShadingPoint::new(intersection, -ray.direction);
+    let ShadingPoint {
+        frame,
+        surface_to_view,
+        ref bsdf,
+        ..
+    } = point;
+
+    let ambient =
+        scene.environment.ambient(frame.normal) * bsdf.reflectance(surface_to_view, frame);
+    let direct = ambient
+        + point.direct_light(scene, Heuristic::Power, sampler)
+        + point.subsurface_light(scene, sampler)
+        + bsdf.emission();
+
+    if depth >= scene.max_depth {
+        return direct;
+    }
+    let lobes: Vec<_> = bsdf
+        .specular_lobes(surface_to_view, frame)
+        .into_iter()
+        .filter(|(_, weight)| weight.luminance() > 0f32)
+        .collect();
+    let follow = |direction: Direction, sampler: &mut Sampler| {
+        let ray = Ray {
+            origin: point.origin_towards(direction),
+            direction,
+            differentials: None,
+        };
+        trace(scene, &ray, sampler, depth + 1)
+    };
+    if depth < SPLIT_SPECULAR_DEPTH {
+        return lobes.into_iter().fold(direct, |acc, (direction, weight)| {
+            acc + weight * follow(direction, sampler)
+        });
+    }
+
+    // Past that, a single lobe is picked in proportion to how much it
+    // carries.
+    let total = lobes
+        .iter()
+        .map(|(_, weight)| weight.luminance())
+        .sum::<f32>();
+    let mut u = sampler.next_1d() * total;
+    let Some(&(direction, weight)) = lobes
+        .iter()
+        .find(|(_, weight)| {
+            u -= weight.luminance();
+            u < 0f32
+        })
+        .or(lobes.last())
+    else {
+        return direct;
+    };
+    direct + weight * follow(direction, sampler) * (total / weight.luminance())
+}
+
+/// A surface hit made ready for shading from `surface_to_view`.
+pub(crate) struct ShadingPoint {
+    pub object_id: ObjectId,
+    pub position: Position,
+    pub real_normal: Direction,
+    /// Around the shading normal, bent towards the viewer if needed.
+    pub frame: ShadingFrame,
+    pub surface_to_view: Direction,
+    pub bsdf: Rc<dyn Bsdf>,
+}
+
+impl ShadingPoint {
+    pub fn new(intersection: Intersection, surface_to_view: Direction) -> ShadingPoint {
+        let adjusted_normal = face_viewer(
+            intersection.shading_normal(),
+            intersection.real_normal,
+            surface_to_view,
+        );
+        let Intersection {
+            object_id,
+            position,
+            real_normal,
+            tangent,
+            context,
+            material,
+        } = intersection;
+        ShadingPoint {
+            object_id,
+            position,
+            real_normal,
+            frame: ShadingFrame::new(real_normal, adjusted_normal, tangent),
+            surface_to_view,
+            bsdf: material.bsdf(&context),
+        }
+    }
+
+    /// Just off the surface on the side its normal points to, where rays
+    /// leaving that side start.
+    pub fn adjusted_position(&self) -> Position {
+        self.position + self.real_normal * EPSILON
+    }
+
+    pub fn behind_position(&self) -> Position {
+        self.position + self.real_normal * -EPSILON
+    }
+
+    /// Where a ray leaving in `direction` starts.
+    pub fn origin_towards(&self, direction: Direction) -> Position {
+        if direction.cos_angle_between(self.real_normal) >= 0f32 {
+            self.adjusted_position()
+        } else {
+            self.behind_position()
+        }
+    }
+
+    /// Light carried out towards the viewer by walks through the medium
+    /// below the surface, if its BSDF has one.
+    pub fn subsurface_light(&self, scene: &Scene, sampler: &mut Sampler) -> HdrColor {
+        let black = HdrColor::new(0f32, 0f32, 0f32);
+        let Some(medium) = self.bsdf.subsurface() else {
+            return black;
+        };
+        let entering = 1f32
+            - fresnel_dielectric(
+                self.real_normal.cos_angle_between(self.surface_to_view),
+                medium.ior,
+            );
+        (0..SUBSURFACE_WALKS)
+            .filter_map(|_| random_walk(scene, self.position, self.real_normal, medium, sampler))
+            .fold(black, |acc, curr| {
+                acc + curr * (entering / SUBSURFACE_WALKS as f32)
+            })
+    }
+
+    /// Light reflected towards the viewer from `scene.lights`, either all
+    /// of them or those its light selection picks.
+    pub fn direct_light(
+        &self,
+        scene: &Scene,
+        heuristic: Heuristic,
+        sampler: &mut Sampler,
+    ) -> HdrColor {
+        sum_over_lights(
+            scene,
+            self.adjusted_position(),
+            self.frame.normal,
+            sampler,
+            |light, sampler| self.light_contribution(scene, light, heuristic, sampler),
+        )
+    }
+
+    /// Light from `light`, estimated once by sampling the light and, unless
+    /// the light has no extent for the BSDF to find, once by sampling the
+    /// BSDF, each weighted by `heuristic`.
+    fn light_contribution(
+        &self,
+        scene: &Scene,
+        light: &dyn Light,
+        heuristic: Heuristic,
+        sampler: &mut Sampler,
+    ) -> HdrColor {
+        let (receiver, world) = (self.object_id, &*scene.world);
+        let (view, frame) = (self.surface_to_view, self.frame);
+        let u = sampler.next_2d();
+        let sample_from = |origin| light.sample(origin, receiver, world, u);
+        // A light can only reach one side, as the surface shadows the other.
+        let light_sample = if self.bsdf.transmits() {
+            sample_from(self.adjusted_position()).or_else(|| sample_from(self.behind_position()))
+        } else {
+            sample_from(self.adjusted_position())
+        };
+
+        let mut total = HdrColor::new(0f32, 0f32, 0f32);
+        if let Some(LightSample {
+            color,
+            direction,
+            pdf,
+            delta,
+        }) = light_sample
+        {
+            let surface_to_light = -direction;
+            let weight = if delta {
+                1f32
+            } else {
+                heuristic.weight(pdf, self.bsdf.pdf(view, surface_to_light, frame))
+            };
+            total = color * self.bsdf.evaluate(view, surface_to_light, frame) * (weight / pdf);
+        }
+        if light.is_delta() {
+            return total;
+        }
+
+        let bsdf_sample = self
+            .bsdf
+            .sample(view, frame, sampler.next_1d(), sampler.next_2d());
+        if let Some(sample) = bsdf_sample {
+            let surface_to_light = sample.surface_to_light;
+            let origin = self.origin_towards(surface_to_light);
+            if let Some(color) = light.emitted(origin, surface_to_light, receiver, world) {
+                let weight = if sample.specular {
+                    1f32
+                } else {
+                    heuristic.weight(sample.pdf, light.pdf(origin, surface_to_light))
+                };
+                total = total + color * sample.value * (weight / sample.pdf);
+            }
+        }
+        total
+    }
+}
+
+/// The sum of `contribution` over all of `scene.lights`, or an estimate of
+/// it from the lights its light selection picks for `position`.
+fn sum_over_lights(
+    scene: &Scene,
+    position: Position,
+    normal: Direction,
+    sampler: &mut Sampler,
+    mut contribution: impl FnMut(&dyn Light, &mut Sampler) -> HdrColor,
+) -> HdrColor {
+    let black = HdrColor::new(0f32, 0f32, 0f32);
+    match &scene.light_selection {
+        None => scene
             .lights
             .iter()
-            .fold(albedo * scene.ambient_light, |acc, curr| {
-                if let Some((color, direction)) = curr.illuminate(adjusted_position, &*scene.world)
-                {
-                    acc + albedo
-                        * color
-                        * brdf(
-                            Direction::from_movement(scene.camera.position() - position),
-                            -direction,
-                            adjusted_normal,
-                            roughness,
-                            f0,
-                        )
-                } else {
-                    acc
-                }
-            }),
-    )
+            .fold(black, |acc, light| acc + contribution(&**light, sampler)),
+        Some(LightSelection {
+            sampler: light_sampler,
+            samples,
+        }) => (0..*samples).fold(black, |acc, _| {
+            let Some((light, pmf)) = light_sampler.sample(position, normal, sampler.next_1d())
+            else {
+                return acc;
+            };
+            acc + contribution(&*scene.lights[light], sampler) * (1f32 / (pmf * *samples as f32))
+        }),
+    }
 }
 
-fn brdf(
-    surface_to_view: Direction,
-    surface_to_light: Direction,
-    surface_normal: Direction,
-    roughness: f32,
-    f0: f32,
-) -> f32 {
-    fn fresnel_schlick(cos_theta: f32, f0: f32) -> f32 {
-        f0 + (1f32 - f0) * (1f32 - cos_theta).powf(5f32)
-    }
-
-    fn ggx_ndf(n: Direction, h: Direction, roughness: f32) -> f32 {
-        let alpha = roughness * roughness;
-        let alpha2 = alpha * alpha;
-        let cos_n_h = n.cos_angle_between(h);
-        let cos_n_h2 = cos_n_h * cos_n_h;
-        let denom = cos_n_h2 * alpha2 + (1f32 - cos_n_h2);
-        alpha2 / (std::f32::consts::PI * denom * denom)
-    }
-
-    fn geometric_attenuation(n: Direction, v: Direction, l: Direction, roughness: f32) -> f32 {
-        let k = (roughness + 1f32) * (roughness + 1f32) / 8f32;
-        let cos_n_v = n.cos_angle_between(v);
-        let g_v = cos_n_v / (cos_n_v * (1f32 - k) + k);
-        let cos_n_l = n.cos_angle_between(l);
-        let g_l = cos_n_l / (cos_n_l * (1f32 - k) + k);
-        g_v * g_l
-    }
-
-    fn cook_torrance_specular(
-        v: Direction,
-        l: Direction,
-        n: Direction,
-        roughness: f32,
-        f0: f32,
-    ) -> f32 {
-        let h = Direction::from_directions([v, l]);
-        let d = ggx_ndf(n, h, roughness);
-        let f = fresnel_schlick(h.cos_angle_between(v), f0);
-        let g = geometric_attenuation(n, v, l, roughness);
-        let specular = (d * f * g) / (4f32 * n.cos_angle_between(v) * n.cos_angle_between(v));
-        specular
-    }
-
-    cook_torrance_specular(
-        surface_to_view,
-        surface_to_light,
-        surface_normal,
-        roughness,
-        f0,
-    )
+/// How multiple importance sampling shares a contribution between two
+/// strategies that can both produce it, after Veach.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Heuristic {
+    Balance,
+    Power,
+}
+
+impl Heuristic {
+    /// Weight of a sample drawn with density `pdf`, which the other
+    /// strategy would have drawn with density `other`.
+    pub fn weight(self, pdf: f32, other: f32) -> f32 {
+        match self {
+            Heuristic::Balance => pdf / (pdf + other),
+            Heuristic::Power => pdf * pdf / (pdf * pdf + other * other),
+        }
+    }
+}
+
+/// Smallest cosine between a shading normal and the view direction.
+const MIN_SHADING_COS: f32 = 0.01f32;
+
+/// Bends a shading normal that the viewer would see from behind towards the
+/// viewer, until it lies just above the normal's horizon, so perturbed
+/// normals never turn black or leak light through the surface.
+fn face_viewer(normal: Direction, real_normal: Direction, surface_to_view: Direction) -> Direction {
+    // Transmissive surfaces can be seen from inside, where both normals
+    // point away from the viewer.
+    let side = if surface_to_view.cos_angle_between(real_normal) >= 0f32 {
+        1f32
+    } else {
+        -1f32
+    };
+    let n = normal.vec * side;
+    let v = surface_to_view.vec;
+    let cos_theta = n.dot(v);
+    if cos_theta >= MIN_SHADING_COS {
+        return normal;
+    }
+    let bent = n + v * (MIN_SHADING_COS - cos_theta);
+    if bent.dot(real_normal.vec * side) <= 0f32 || bent.dot(bent) < EPSILON {
+        return real_normal;
+    }
+    Direction {
+        vec: bent.normalize() * side,
+    }
+}
+
+/// Follows light entering the surface at `position` through the medium
+/// below it, returning the radiance it carries in if it finds its way out.
+fn random_walk(
+    scene: &Scene,
+    position: Position,
+    real_normal: Direction,
+    medium: SubsurfaceMedium,
+    sampler: &mut Sampler,
+) -> Option<HdrColor> {
+    let mean_free_path = medium.mean_free_path;
+    let albedo = medium.single_scattering_albedo;
+    let sigma_t = [mean_free_path.r, mean_free_path.g, mean_free_path.b]
+        .map(|mean_free_path| 1f32 / mean_free_path.max(EPSILON));
+    let albedo = [albedo.r, albedo.g, albedo.b];
+    let mut throughput = [1f32; 3];
+    let mut ray = Ray {
+        origin: position + real_normal * -EPSILON,
+        direction: sample_cosine_hemisphere(-real_normal, sampler.next_2d()),
+        differentials: None,
+    };
+    for _ in 0..MAX_SUBSURFACE_BOUNCES {
+        // Distances are sampled from one channel at a time and weighted by
+        // the average density of all three.
+        let channel = ((sampler.next_1d() * 3f32) as usize).min(2);
+        let distance = -(1f32 - sampler.next_1d()).ln() / sigma_t[channel];
+        let exit = scene.world.intersect(&ray).and_then(|intersection| {
+            let hit_distance = (intersection.position - ray.origin).distance();
+            (hit_distance < distance).then_some((intersection, hit_distance))
+        });
+        if let Some((intersection, hit_distance)) = exit {
+            let transmittance = sigma_t.map(|sigma_t| (-sigma_t * hit_distance).exp());
+            let pdf = transmittance.iter().sum::<f32>() / 3f32;
+            for (throughput, transmittance) in throughput.iter_mut().zip(transmittance) {
+                *throughput *= transmittance / pdf;
+            }
+            let outward = if intersection.real_normal.cos_angle_between(ray.direction) > 0f32 {
+                intersection.real_normal
+            } else {
+                -intersection.real_normal
+            };
+            let [r, g, b] = throughput;
+            return Some(
+                HdrColor::new(r, g, b)
+                    * diffuse_light(
+                        scene,
+                        intersection.position + outward * EPSILON,
+                        outward,
+                        intersection.object_id,
+                        sampler,
+                    ),
+            );
+        }
+        let density = sigma_t.map(|sigma_t| sigma_t * (-sigma_t * distance).exp());
+        let pdf = density.iter().sum::<f32>() / 3f32;
+        for ((throughput, density), albedo) in throughput.iter_mut().zip(density).zip(albedo) {
+            *throughput *= albedo * density / pdf;
+        }
+        ray = Ray {
+            origin: ray.origin + ray.direction * distance,
+            direction: sample_uniform_sphere(sampler.next_2d()),
+            differentials: None,
+        };
+    }
+    None
+}
+
+/// Radiance leaving a white Lambertian surface at `position`, lit by the
+/// lights the scene's light selection picks.
+fn diffuse_light(
+    scene: &Scene,
+    position: Position,
+    normal: Direction,
+    receiver: ObjectId,
+    sampler: &mut Sampler,
+) -> HdrColor {
+    let direct = sum_over_lights(scene, position, normal, sampler, |light, _| {
+        let Some((color, direction)) = light.illuminate(position, receiver, &*scene.world) else {
+            return HdrColor::new(0f32, 0f32, 0f32);
+        };
+        let cos_theta = normal.cos_angle_between(-direction).max(0f32);
+        color * (cos_theta / std::f32::consts::PI)
+    });
+    scene.environment.ambient(normal) + direct
 }
 
 pub struct HdrImage {
@@ -105,16 +480,26 @@ pub struct HdrImage {
     pub content: Vec<Option<HdrColor>>,
 }
 
-pub fn render_hdr_image(scene: &Scene, width: usize, height: usize) -> HdrImage {
+pub fn render_hdr_image<I: Integrator + ?Sized>(
+    scene: &Scene,
+    integrator: &I,
+    width: usize,
+    height: usize,
+    samples_per_pixel: usize,
+) -> HdrImage {
     let mut content = Vec::new();
     for y in 0..height {
         for x in 0..width {
             content.push(render(
                 scene,
+                integrator,
                 (
                     x as f32 / (width - 1) as f32,
                     1.0f32 - y as f32 / (height - 1) as f32,
                 ),
+                (1f32 / (width - 1) as f32, 1f32 / (height - 1) as f32),
+                samples_per_pixel,
+                &mut Sampler::new((y * width + x) as u64),
             ))
         }
     }
@@ -200,3 +585,259 @@ pub fn render_ldr_image(
         sqrt_super_sampling_rate,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base_types::Transform;
+    use bsdf::{CookTorrance, Dielectric, Emissive, Subsurface, F0_SILVER};
+    use scene::{Object, PerspectiveCamera, Plane, PointLight, SphereLight};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_white_subsurface_returns_light() {
+        let scene = Scene::new(
+            Box::new(PerspectiveCamera::by_x(
+                Position::new(0f32, 0f32, 0f32),
+                0f32,
+                0f32,
+                0f32,
+                1f32,
+                1f32,
+            )),
+            Box::new(Plane::new(
+                scene::ObjectId(0),
+                Transform::I,
+                1f32,
+                0f32,
+                0f32,
+                1f32,
+                Rc::new(Subsurface {
+                    color: LdrColor::new(1f32, 1f32, 1f32),
+                    mean_free_path: HdrColor::new(0.1f32, 0.05f32, 0.02f32),
+                    ior: 1.4f32,
+                    roughness: 0.5f32,
+                }),
+            )),
+            Box::new(HdrColor::new(1f32, 1f32, 1f32)),
+            vec![],
+        );
+        let mut sampler = Sampler::new(42);
+        let samples = 64;
+        let mut average = 0f32;
+        for _ in 0..samples {
+            average += render(
+                &scene,
+                &Whitted,
+                (0.5f32, 0.2f32),
+                (0.01f32, 0.01f32),
+                1,
+                &mut sampler,
+            )
+            .unwrap()
+            .g;
+        }
+        average /= samples as f32;
+        assert!((average - 1f32).abs() < 0.15f32);
+    }
+
+    #[test]
+    fn test_mirror_reflects_ceiling() {
+        let black = LdrColor::new(0f32, 0f32, 0f32);
+        // A perfectly smooth floor, lit by a light it must not turn into
+        // NaNs.
+        let mut scene = Scene::new(
+            Box::new(PerspectiveCamera::by_x(
+                Position::new(0f32, 0f32, 0f32),
+                0f32,
+                0f32,
+                0f32,
+                1f32,
+                1f32,
+            )),
+            Box::new(vec![
+                Box::new(Plane::new(
+                    scene::ObjectId(0),
+                    Transform::I,
+                    1f32,
+                    0f32,
+                    0f32,
+                    1f32,
+                    Rc::new(CookTorrance {
+                        albedo: black,
+                        roughness_u: 0f32,
+                        roughness_v: 0f32,
+                        f0: F0_SILVER,
+                        thin_film: None,
+                    }),
+                )) as Box<dyn scene::Object>,
+                Box::new(Plane::new(
+                    scene::ObjectId(1),
+                    Transform::I,
+                    -1f32,
+                    0f32,
+                    0f32,
+                    1f32,
+                    Rc::new(Emissive {
+                        base: CookTorrance {
+                            albedo: black,
+                            roughness_u: 0.5f32,
+                            roughness_v: 0.5f32,
+                            f0: black,
+                            thin_film: None,
+                        },
+                        emission: HdrColor::new(1f32, 1f32, 1f32),
+                    }),
+                )),
+            ]),
+            Box::new(HdrColor::new(0f32, 0f32, 0f32)),
+            // Off the mirror direction, so only evaluated, not seen.
+            vec![Box::new(PointLight::new(
+                Position::new(0f32, 5f32, 0.5f32),
+                HdrColor::new(1f32, 1f32, 1f32),
+            ))],
+        );
+        let mut sampler = Sampler::new(42);
+        let mut floor = |scene: &Scene| {
+            render(
+                scene,
+                &Whitted,
+                (0.5f32, 0.2f32),
+                (0.01f32, 0.01f32),
+                1,
+                &mut sampler,
+            )
+            .unwrap()
+            .g
+        };
+
+        assert!((floor(&scene) - F0_SILVER.g).abs() < 0.01f32);
+        scene.max_depth = 0;
+        assert!(floor(&scene) < 0.00042f32);
+    }
+
+    #[test]
+    fn test_glass_reflects_and_refracts_at_primary_hits() {
+        let black = LdrColor::new(0f32, 0f32, 0f32);
+        let glowing = |id: u32, offset: f32, emission: f32| {
+            Box::new(Plane::new(
+                scene::ObjectId(id),
+                Transform::I,
+                offset,
+                0f32,
+                0f32,
+                1f32,
+                Rc::new(Emissive {
+                    base: CookTorrance {
+                        albedo: black,
+                        roughness_u: 0.5f32,
+                        roughness_v: 0.5f32,
+                        f0: black,
+                        thin_film: None,
+                    },
+                    emission: HdrColor::new(emission, emission, emission),
+                }),
+            )) as Box<dyn scene::Object>
+        };
+        let scene = Scene::new(
+            Box::new(PerspectiveCamera::by_x(
+                Position::new(0f32, 0f32, 0f32),
+                0f32,
+                0f32,
+                0f32,
+                1f32,
+                1f32,
+            )),
+            Box::new(vec![
+                Box::new(Plane::new(
+                    scene::ObjectId(0),
+                    Transform::I,
+                    1f32,
+                    0f32,
+                    0f32,
+                    1f32,
+                    Rc::new(Dielectric {
+                        ior: 1.5f32,
+                        transmittance: LdrColor::new(1f32, 1f32, 1f32),
+                    }),
+                )) as Box<dyn scene::Object>,
+                glowing(1, -1f32, 1f32),
+                glowing(2, 2f32, 2f32),
+            ]),
+            Box::new(HdrColor::new(0f32, 0f32, 0f32)),
+            vec![],
+        );
+        let (position, pixel_size) = ((0.5f32, 0.2f32), (0.01f32, 0.01f32));
+        let ray = scene
+            .camera
+            .get_ray_with_differentials(position, pixel_size);
+        let f = fresnel_dielectric(-ray.direction.vec.2, 1.5f32);
+        // The ceiling is mirrored, and the plane below seen through the
+        // glass with its radiance compressed by the squared index.
+        let expected = f * 1f32 + (1f32 - f) * 2f32 / (1.5f32 * 1.5f32);
+        let actual = render(
+            &scene,
+            &Whitted,
+            position,
+            pixel_size,
+            1,
+            &mut Sampler::new(42),
+        )
+        .unwrap();
+        assert!((actual.g - expected).abs() < 0.00042f32);
+    }
+
+    #[test]
+    fn test_sphere_light_with_both_heuristics() {
+        let floor = Plane::new(
+            scene::ObjectId(0),
+            Transform::I,
+            1f32,
+            0f32,
+            0f32,
+            1f32,
+            Rc::new(CookTorrance {
+                albedo: LdrColor::new(1f32, 1f32, 1f32),
+                roughness_u: 0.5f32,
+                roughness_v: 0.5f32,
+                f0: LdrColor::new(0f32, 0f32, 0f32),
+                thin_film: None,
+            }),
+        );
+        let down = Ray {
+            origin: Position::new(0f32, 0f32, 0f32),
+            direction: Direction::from_movement(base_types::Movement::new(0f32, 0f32, -1f32)),
+            differentials: None,
+        };
+        let point = ShadingPoint::new(floor.intersect(&down).unwrap(), -down.direction);
+        let scene = Scene::new(
+            Box::new(PerspectiveCamera::by_x(
+                Position::new(0f32, 0f32, 0f32),
+                0f32,
+                0f32,
+                0f32,
+                1f32,
+                1f32,
+            )),
+            Box::new(floor),
+            Box::new(HdrColor::new(0f32, 0f32, 0f32)),
+            vec![Box::new(SphereLight::new(
+                Position::new(0f32, 0f32, 1f32),
+                0.5f32,
+                HdrColor::new(16f32, 16f32, 16f32),
+            ))],
+        );
+
+        // A white Lambertian surface facing a sphere of radiance L, radius r
+        // and distance d sends back L r^2 / d^2.
+        let mut sampler = Sampler::new(42);
+        for heuristic in [Heuristic::Balance, Heuristic::Power] {
+            let samples = 4200;
+            let average = (0..samples)
+                .map(|_| point.direct_light(&scene, heuristic, &mut sampler).g)
+                .sum::<f32>()
+                / samples as f32;
+            assert!((average - 1f32).abs() < 0.02f32);
+        }
+    }
+}
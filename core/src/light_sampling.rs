@@ -0,0 +1,498 @@
+use crate::{
+    base_types::{BoundingBox, Direction, Position},
+    math::Vec3,
+    scene::Light,
+};
+
+/// Where and in which directions a light emits, and how much.
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub bounds: BoundingBox,
+    pub power: f32,
+    pub axis: Direction,
+    /// Cosine of the spread of emitting normals around `axis`.
+    pub cos_theta_o: f32,
+    /// Cosine of the angle past those normals at which emission falls to zero.
+    pub cos_theta_e: f32,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn union(self, rhs: LightBounds) -> LightBounds {
+        if self.power == 0f32 {
+            return rhs;
+        }
+        if rhs.power == 0f32 {
+            return self;
+        }
+        let (axis, cos_theta_o) =
+            union_cones((self.axis, self.cos_theta_o), (rhs.axis, rhs.cos_theta_o));
+        LightBounds {
+            bounds: self.bounds.union(rhs.bounds),
+            power: self.power + rhs.power,
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(rhs.cos_theta_e),
+            two_sided: self.two_sided || rhs.two_sided,
+        }
+    }
+
+    /// Conservative estimate of the light reaching `position`, a point on a
+    /// surface facing `normal`, following Conty Estevez and Kulla.
+    pub fn importance(&self, position: Position, normal: Direction) -> f32 {
+        let center = self.bounds.center();
+        let radius = self.bounds.diagonal().distance() / 2f32;
+        let distance_squared = (position - center)
+            .distance_squared()
+            .max(radius * radius)
+            .max(f32::MIN_POSITIVE);
+
+        let to_position = Direction::from_movement(position - center);
+        let mut cos_theta_w = self.axis.cos_angle_between(to_position);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1f32 - cos_theta_w * cos_theta_w);
+
+        let cos_theta_b = if self.bounds.contains(position) {
+            -1f32
+        } else {
+            safe_sqrt(1f32 - radius * radius / distance_squared)
+        };
+        let sin_theta_b = safe_sqrt(1f32 - cos_theta_b * cos_theta_b);
+
+        let sin_theta_o = safe_sqrt(1f32 - self.cos_theta_o * self.cos_theta_o);
+        let (cos_theta_x, sin_theta_x) =
+            cos_sin_sub_clamped((cos_theta_w, sin_theta_w), (self.cos_theta_o, sin_theta_o));
+        let (cos_theta_p, _) =
+            cos_sin_sub_clamped((cos_theta_x, sin_theta_x), (cos_theta_b, sin_theta_b));
+        if cos_theta_p <= self.cos_theta_e {
+            return 0f32;
+        }
+
+        let cos_theta_i = normal.cos_angle_between(to_position).abs();
+        let sin_theta_i = safe_sqrt(1f32 - cos_theta_i * cos_theta_i);
+        let (cos_theta_pi, _) =
+            cos_sin_sub_clamped((cos_theta_i, sin_theta_i), (cos_theta_b, sin_theta_b));
+
+        (self.power * cos_theta_p * cos_theta_pi / distance_squared).max(0f32)
+    }
+}
+
+fn safe_sqrt(f: f32) -> f32 {
+    f.max(0f32).sqrt()
+}
+
+/// Cosine and sine of `a - b`, clamped to zero when `b` exceeds `a`.
+fn cos_sin_sub_clamped(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    let (cos_a, sin_a) = a;
+    let (cos_b, sin_b) = b;
+    if cos_a > cos_b {
+        (1f32, 0f32)
+    } else {
+        (cos_a * cos_b + sin_a * sin_b, sin_a * cos_b - cos_a * sin_b)
+    }
+}
+
+fn union_cones(a: (Direction, f32), b: (Direction, f32)) -> (Direction, f32) {
+    let (axis_a, cos_a) = a;
+    let (axis_b, cos_b) = b;
+    let theta_a = cos_a.clamp(-1f32, 1f32).acos();
+    let theta_b = cos_b.clamp(-1f32, 1f32).acos();
+    let theta_d = axis_a.cos_angle_between(axis_b).clamp(-1f32, 1f32).acos();
+    if (theta_d + theta_b).min(std::f32::consts::PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(std::f32::consts::PI) <= theta_b {
+        return b;
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2f32;
+    if theta_o >= std::f32::consts::PI {
+        return (axis_a, -1f32);
+    }
+    let rotation_axis = axis_a.perpendicular_to(axis_b).vec;
+    if rotation_axis.length_squared() == 0f32 {
+        return (axis_a, -1f32);
+    }
+    let rotation_axis = rotation_axis.normalize();
+    let theta_r = theta_o - theta_a;
+    // Rodrigues' rotation of axis_a towards axis_b by theta_r.
+    let v = axis_a.vec;
+    let rotated = v * theta_r.cos()
+        + rotation_axis.cross(v) * theta_r.sin()
+        + rotation_axis * (rotation_axis.dot(v) * (1f32 - theta_r.cos()));
+    (
+        Direction {
+            vec: rotated.normalize(),
+        },
+        theta_o.cos(),
+    )
+}
+
+/// Chooses one of a scene's lights for a shading point, returning its index
+/// and the probability with which it was chosen.
+pub trait LightSampler {
+    fn sample(&self, position: Position, normal: Direction, u: f32) -> Option<(usize, f32)>;
+    fn pmf(&self, position: Position, normal: Direction, light: usize) -> f32;
+}
+
+pub struct UniformLightSampler {
+    count: usize,
+}
+
+impl UniformLightSampler {
+    pub fn new(lights: &[Box<dyn Light>]) -> UniformLightSampler {
+        UniformLightSampler {
+            count: lights.len(),
+        }
+    }
+}
+
+impl LightSampler for UniformLightSampler {
+    fn sample(&self, _position: Position, _normal: Direction, u: f32) -> Option<(usize, f32)> {
+        if self.count == 0 {
+            return None;
+        }
+        let light = ((u * self.count as f32) as usize).min(self.count - 1);
+        Some((light, 1f32 / self.count as f32))
+    }
+
+    fn pmf(&self, _position: Position, _normal: Direction, _light: usize) -> f32 {
+        if self.count == 0 {
+            0f32
+        } else {
+            1f32 / self.count as f32
+        }
+    }
+}
+
+pub struct PowerLightSampler {
+    cdf: Vec<f32>,
+    pmf: Vec<f32>,
+}
+
+impl PowerLightSampler {
+    pub fn new(lights: &[Box<dyn Light>]) -> PowerLightSampler {
+        let total = lights.iter().map(|light| light.power()).sum::<f32>();
+        let pmf = if total > 0f32 {
+            lights.iter().map(|light| light.power() / total).collect()
+        } else {
+            vec![1f32 / lights.len() as f32; lights.len()]
+        };
+        let cdf = pmf
+            .iter()
+            .scan(0f32, |acc, p| {
+                *acc += p;
+                Some(*acc)
+            })
+            .collect();
+        PowerLightSampler { cdf, pmf }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn sample(&self, _position: Position, _normal: Direction, u: f32) -> Option<(usize, f32)> {
+        if self.pmf.is_empty() {
+            return None;
+        }
+        let light = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.pmf.len() - 1);
+        Some((light, self.pmf[light]))
+    }
+
+    fn pmf(&self, _position: Position, _normal: Direction, light: usize) -> f32 {
+        self.pmf[light]
+    }
+}
+
+enum LightBvhNode {
+    Leaf {
+        light: usize,
+        bounds: LightBounds,
+    },
+    Interior {
+        bounds: LightBounds,
+        children: [usize; 2],
+    },
+}
+
+impl LightBvhNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightBvhNode::Leaf { bounds, .. } | LightBvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Samples bounded lights by walking a tree of `LightBounds`, choosing each
+/// child in proportion to its importance for the shading point. Lights
+/// without bounds are sampled uniformly alongside the tree.
+pub struct BvhLightSampler {
+    nodes: Vec<LightBvhNode>,
+    infinite_lights: Vec<usize>,
+    /// Bits of the child choices leading from the root to each light's leaf.
+    bit_trails: Vec<Option<u64>>,
+}
+
+impl BvhLightSampler {
+    pub fn new(lights: &[Box<dyn Light>]) -> BvhLightSampler {
+        let mut infinite_lights = Vec::new();
+        let mut bounded_lights = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.power > 0f32 => bounded_lights.push((index, bounds)),
+                Some(_) => {}
+                None => infinite_lights.push(index),
+            }
+        }
+
+        let mut sampler = BvhLightSampler {
+            nodes: Vec::new(),
+            infinite_lights,
+            bit_trails: vec![None; lights.len()],
+        };
+        if !bounded_lights.is_empty() {
+            sampler.build(&mut bounded_lights, 0, 0);
+        }
+        sampler
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], bit_trail: u64, depth: u32) -> usize {
+        if let [(light, bounds)] = *lights {
+            self.bit_trails[light] = Some(bit_trail);
+            self.nodes.push(LightBvhNode::Leaf { light, bounds });
+            return self.nodes.len() - 1;
+        }
+
+        let centroids = lights
+            .iter()
+            .map(|(_, bounds)| BoundingBox::from_position(bounds.bounds.center()))
+            .reduce(BoundingBox::union)
+            .unwrap();
+        let Vec3(x, y, z) = centroids.diagonal().vec;
+        let axis = if x >= y && x >= z {
+            0
+        } else if y >= z {
+            1
+        } else {
+            2
+        };
+        let component = |bounds: &LightBounds| {
+            let Vec3(x, y, z) = bounds.bounds.center().vec;
+            [x, y, z][axis]
+        };
+        lights.sort_by(|(_, a), (_, b)| component(a).total_cmp(&component(b)));
+
+        let middle = lights.len() / 2;
+        let (left, right) = lights.split_at_mut(middle);
+        let index = self.nodes.len();
+        self.nodes.push(LightBvhNode::Interior {
+            bounds: left
+                .iter()
+                .chain(right.iter())
+                .map(|(_, bounds)| *bounds)
+                .reduce(LightBounds::union)
+                .unwrap(),
+            children: [0, 0],
+        });
+        debug_assert!(depth < 64);
+        let first = self.build(left, bit_trail, depth + 1);
+        let second = self.build(right, bit_trail | (1u64 << depth), depth + 1);
+        if let LightBvhNode::Interior { children, .. } = &mut self.nodes[index] {
+            *children = [first, second];
+        }
+        index
+    }
+
+    fn infinite_probability(&self) -> f32 {
+        let bvh_count = if self.nodes.is_empty() { 0f32 } else { 1f32 };
+        let infinite_count = self.infinite_lights.len() as f32;
+        if infinite_count + bvh_count == 0f32 {
+            0f32
+        } else {
+            infinite_count / (infinite_count + bvh_count)
+        }
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn sample(&self, position: Position, normal: Direction, u: f32) -> Option<(usize, f32)> {
+        let infinite_probability = self.infinite_probability();
+        if u < infinite_probability {
+            let count = self.infinite_lights.len();
+            let chosen = ((u / infinite_probability * count as f32) as usize).min(count - 1);
+            return Some((
+                self.infinite_lights[chosen],
+                infinite_probability / count as f32,
+            ));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut u = ((u - infinite_probability) / (1f32 - infinite_probability)).min(1f32);
+        let mut pmf = 1f32 - infinite_probability;
+        let mut node = 0;
+        loop {
+            match &self.nodes[node] {
+                LightBvhNode::Leaf { light, bounds } => {
+                    return if bounds.importance(position, normal) > 0f32 {
+                        Some((*light, pmf))
+                    } else {
+                        None
+                    };
+                }
+                LightBvhNode::Interior { children, .. } => {
+                    let first = self.nodes[children[0]]
+                        .bounds()
+                        .importance(position, normal);
+                    let second = self.nodes[children[1]]
+                        .bounds()
+                        .importance(position, normal);
+                    if first == 0f32 && second == 0f32 {
+                        return None;
+                    }
+                    let p_first = first / (first + second);
+                    if u < p_first {
+                        node = children[0];
+                        u = (u / p_first).min(1f32);
+                        pmf *= p_first;
+                    } else {
+                        node = children[1];
+                        u = ((u - p_first) / (1f32 - p_first)).min(1f32);
+                        pmf *= 1f32 - p_first;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, position: Position, normal: Direction, light: usize) -> f32 {
+        let infinite_probability = self.infinite_probability();
+        let Some(mut bit_trail) = self.bit_trails[light] else {
+            return if self.infinite_lights.contains(&light) {
+                infinite_probability / self.infinite_lights.len() as f32
+            } else {
+                0f32
+            };
+        };
+
+        let mut pmf = 1f32 - infinite_probability;
+        let mut node = 0;
+        while let LightBvhNode::Interior { children, .. } = &self.nodes[node] {
+            let importance = [
+                self.nodes[children[0]]
+                    .bounds()
+                    .importance(position, normal),
+                self.nodes[children[1]]
+                    .bounds()
+                    .importance(position, normal),
+            ];
+            let chosen = (bit_trail & 1) as usize;
+            if importance[chosen] == 0f32 {
+                return 0f32;
+            }
+            pmf *= importance[chosen] / (importance[0] + importance[1]);
+            node = children[chosen];
+            bit_trail >>= 1;
+        }
+        pmf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::base_types::{HdrColor, Movement};
+    use crate::scene::{DirectionalLight, PointLight};
+
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    fn random_lights(rng: &mut StdRng) -> Vec<Box<dyn Light>> {
+        let mut lights: Vec<Box<dyn Light>> = (0..42)
+            .map(|_| {
+                Box::new(PointLight::new(
+                    Position::new(
+                        rng.gen_range(-10.0..10.0),
+                        rng.gen_range(-10.0..10.0),
+                        rng.gen_range(-10.0..10.0),
+                    ),
+                    HdrColor::new(
+                        rng.gen_range(0.0..4.0),
+                        rng.gen_range(0.0..4.0),
+                        rng.gen_range(0.0..4.0),
+                    ),
+                )) as Box<dyn Light>
+            })
+            .collect();
+        lights.push(Box::new(DirectionalLight::new(
+            Direction::from_movement(Movement::new(0f32, 0f32, -1f32)),
+            HdrColor::new(1f32, 1f32, 1f32),
+            12f32,
+        )));
+        lights
+    }
+
+    #[test]
+    fn test_bvh_light_sampler_pmf_is_consistent() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let lights = random_lights(&mut rng);
+        let sampler = BvhLightSampler::new(&lights);
+
+        for _ in 0..42 {
+            let position = Position::new(
+                rng.gen_range(-12.0..12.0),
+                rng.gen_range(-12.0..12.0),
+                rng.gen_range(-12.0..12.0),
+            );
+            let normal = Direction::from_movement(Movement::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            ));
+
+            let total = (0..lights.len())
+                .map(|light| sampler.pmf(position, normal, light))
+                .sum::<f32>();
+            assert!((total - 1f32).abs() < 0.00042f32);
+
+            if let Some((light, pmf)) = sampler.sample(position, normal, rng.gen()) {
+                assert!((pmf - sampler.pmf(position, normal, light)).abs() < 0.00042f32);
+            }
+        }
+    }
+
+    #[test]
+    fn test_light_samplers_pick_lights_by_pmf() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let lights = random_lights(&mut rng);
+        let samplers: [Box<dyn LightSampler>; 3] = [
+            Box::new(UniformLightSampler::new(&lights)),
+            Box::new(PowerLightSampler::new(&lights)),
+            Box::new(BvhLightSampler::new(&lights)),
+        ];
+        let position = Position::new(1f32, 2f32, 3f32);
+        let normal = Direction::from_movement(Movement::new(0f32, 0.6f32, 0.8f32));
+
+        let samples = 42000;
+        for sampler in &samplers {
+            let mut counts = vec![0usize; lights.len()];
+            for _ in 0..samples {
+                if let Some((light, _)) = sampler.sample(position, normal, rng.gen()) {
+                    counts[light] += 1;
+                }
+            }
+            for (light, &count) in counts.iter().enumerate() {
+                let frequency = count as f32 / samples as f32;
+                let pmf = sampler.pmf(position, normal, light);
+                assert!((frequency - pmf).abs() < 0.005f32, "{frequency} != {pmf}");
+            }
+        }
+    }
+}
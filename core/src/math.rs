@@ -25,7 +25,7 @@ impl std::ops::Sub<Vec3> for Vec3 {
     type Output = Vec3;
 
     fn sub(self, rhs: Vec3) -> Vec3 {
-        Vec3(self.0 / rhs.0, self.1 / rhs.1, self.2 / rhs.2)
+        Vec3(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
     }
 }
 
@@ -231,12 +231,8 @@ impl std::ops::Mul<Mat4> for Vec4 {
     type Output = Vec4;
 
     fn mul(self, rhs: Mat4) -> Vec4 {
-        Vec4(
-            self.0 * rhs.0 .0 + self.0 * rhs.1 .0 * self.0 * rhs.2 .0,
-            self.1 * rhs.0 .1 + self.1 * rhs.1 .1 + self.1 * rhs.2 .1,
-            self.2 * rhs.0 .2 * self.2 * rhs.1 .2 + self.2 * rhs.2 .2,
-            self.3 * rhs.0 .3 * self.3 * rhs.1 .3 * self.3 * rhs.2 .3 + self.3,
-        )
+        let row = |Vec4(a, b, c, d): Vec4| a * self.0 + b * self.1 + c * self.2 + d * self.3;
+        Vec4(row(rhs.0), row(rhs.1), row(rhs.2), self.3)
     }
 }
 
@@ -303,8 +299,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_vec4_transformed_by_mat4() {
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let moved =
+            Vec4::from_position(Vec3(1f32, 2f32, 3f32)) * Mat4::translate(1f32, 0f32, -1f32);
+        assert!((moved.0 - 2f32).abs() < 0.00042f32);
+        assert!((moved.2 - 2f32).abs() < 0.00042f32);
+        let turned = Vec4::from_movement(Vec3(1f32, 0f32, 0f32))
+            * Mat4::rotate_z_by_angle(std::f32::consts::FRAC_PI_2);
+        assert!((turned.1 - 1f32).abs() < 0.00042f32);
+
+        for _ in 0..42 {
+            let mat = random_transformation_composition_mat4(&mut rng);
+            let point = Vec4::from_position(Vec3(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            ));
+            let result = point * mat * mat.inverse();
+            assert!((result.0 - point.0).abs() < 0.00042f32 * 100f32);
+            assert!((result.1 - point.1).abs() < 0.00042f32 * 100f32);
+            assert!((result.2 - point.2).abs() < 0.00042f32 * 100f32);
+        }
+    }
+
     fn approx_eq_mat4(a: Mat4, b: Mat4, epsilon: f32) -> bool {
-        true && (a.0 .0 - b.0 .0).abs() < epsilon
+        (a.0 .0 - b.0 .0).abs() < epsilon
             && (a.0 .1 - b.0 .1).abs() < epsilon
             && (a.0 .2 - b.0 .2).abs() < epsilon
             && (a.0 .3 - b.0 .3).abs() < epsilon
@@ -0,0 +1,343 @@
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    io::{Error, ErrorKind, Read},
+    path::Path,
+};
+
+use crate::{
+    base_types::{Direction, HdrColor},
+    bsdf::{sample_cosine_hemisphere, Bsdf, BsdfSample, ShadingFrame},
+    math::Vec3,
+};
+
+const THETA_HALF_STEPS: usize = 90;
+const THETA_DIFFERENCE_STEPS: usize = 90;
+const PHI_DIFFERENCE_STEPS: usize = 180;
+const TABLE_SIZE: usize = THETA_HALF_STEPS * THETA_DIFFERENCE_STEPS * PHI_DIFFERENCE_STEPS;
+
+/// Factors converting the stored values of each channel into reflectance.
+const CHANNEL_SCALES: [f64; 3] = [1f64 / 1500f64, 1.15f64 / 1500f64, 1.66f64 / 1500f64];
+
+/// Probability of sampling a cosine-weighted direction instead of the
+/// tabulated half vector distribution, covering what the fit misses.
+const DIFFUSE_SAMPLING_PROBABILITY: f32 = 0.1f32;
+
+const ALBEDO_STEPS: usize = 8;
+
+/// A reflectance measurement in the format of the MERL BRDF database,
+/// indexed by the angles between the half vector and the normal and between
+/// the light and the half vector, following Rusinkiewicz's parameterisation.
+pub struct MeasuredBrdf {
+    table: Vec<[f32; 3]>,
+    /// Cumulative probability of sampling each half angle bin.
+    theta_half_cdf: Vec<f32>,
+    /// Reflectance of uniform light by cosine of the view angle.
+    albedo: [HdrColor; ALBEDO_STEPS],
+}
+
+impl MeasuredBrdf {
+    pub fn load(path: impl AsRef<Path>) -> Result<MeasuredBrdf, Error> {
+        MeasuredBrdf::read(std::fs::File::open(path)?)
+    }
+
+    /// Reads the dimensions as three little-endian 32 bit integers followed
+    /// by the red, green and blue tables as 64 bit floats.
+    pub fn read(mut reader: impl Read) -> Result<MeasuredBrdf, Error> {
+        let mut dimensions = [0u8; 12];
+        reader.read_exact(&mut dimensions)?;
+        let dimension = |i: usize| {
+            i32::from_le_bytes([
+                dimensions[i * 4],
+                dimensions[i * 4 + 1],
+                dimensions[i * 4 + 2],
+                dimensions[i * 4 + 3],
+            ])
+        };
+        if (dimension(0), dimension(1), dimension(2))
+            != (
+                THETA_HALF_STEPS as i32,
+                THETA_DIFFERENCE_STEPS as i32,
+                PHI_DIFFERENCE_STEPS as i32,
+            )
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "unexpected MERL table dimensions",
+            ));
+        }
+
+        let mut bytes = vec![0u8; TABLE_SIZE * 3 * 8];
+        reader.read_exact(&mut bytes)?;
+        let mut table = vec![[0f32; 3]; TABLE_SIZE];
+        for (channel, scale) in CHANNEL_SCALES.iter().enumerate() {
+            for (i, entry) in table.iter_mut().enumerate() {
+                let offset = (channel * TABLE_SIZE + i) * 8;
+                let mut value = [0u8; 8];
+                value.copy_from_slice(&bytes[offset..offset + 8]);
+                // Unmeasured entries are stored as negative numbers.
+                entry[channel] = (f64::from_le_bytes(value) * scale).max(0f64) as f32;
+            }
+        }
+        Ok(MeasuredBrdf::new(table))
+    }
+
+    fn new(table: Vec<[f32; 3]>) -> MeasuredBrdf {
+        // Each half angle bin is weighted by its average luminance times the
+        // solid angle of half vectors it covers.
+        let mut theta_half_cdf = Vec::with_capacity(THETA_HALF_STEPS);
+        let mut total = 0f32;
+        let slice = THETA_DIFFERENCE_STEPS * PHI_DIFFERENCE_STEPS;
+        for i in 0..THETA_HALF_STEPS {
+            let luminance = table[i * slice..(i + 1) * slice]
+                .iter()
+                .map(|&[r, g, b]| HdrColor::new(r, g, b).luminance())
+                .sum::<f32>()
+                / slice as f32;
+            let (low, high) = theta_half_bin(i);
+            total += luminance * (low.cos() - high.cos());
+            theta_half_cdf.push(total);
+        }
+        for cumulative in theta_half_cdf.iter_mut() {
+            *cumulative = if total > 0f32 {
+                *cumulative / total
+            } else {
+                1f32
+            };
+        }
+
+        let mut brdf = MeasuredBrdf {
+            table,
+            theta_half_cdf,
+            albedo: [HdrColor::new(0f32, 0f32, 0f32); ALBEDO_STEPS],
+        };
+        brdf.albedo = brdf.tabulate_albedo();
+        brdf
+    }
+
+    fn tabulate_albedo(&self) -> [HdrColor; ALBEDO_STEPS] {
+        const THETA_STEPS: usize = 16;
+        const PHI_STEPS: usize = 32;
+        let mut albedo = [HdrColor::new(0f32, 0f32, 0f32); ALBEDO_STEPS];
+        let d_theta = FRAC_PI_2 / THETA_STEPS as f32;
+        let d_phi = 2f32 * PI / PHI_STEPS as f32;
+        for (i, albedo) in albedo.iter_mut().enumerate() {
+            let cos_v = (i as f32 + 0.5f32) / ALBEDO_STEPS as f32;
+            let v = Vec3((1f32 - cos_v * cos_v).sqrt(), 0f32, cos_v);
+            for j in 0..THETA_STEPS {
+                let theta = (j as f32 + 0.5f32) * d_theta;
+                for k in 0..PHI_STEPS {
+                    let phi = (k as f32 + 0.5f32) * d_phi;
+                    let l = Vec3(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    let [r, g, b] = self.lookup(v, l);
+                    let weight = theta.cos() * theta.sin() * d_theta * d_phi;
+                    *albedo = *albedo + HdrColor::new(r, g, b) * weight;
+                }
+            }
+        }
+        albedo
+    }
+
+    /// The BRDF for directions given in the local shading frame.
+    fn lookup(&self, v: Vec3, l: Vec3) -> [f32; 3] {
+        if v.2 <= 0f32 || l.2 <= 0f32 {
+            return [0f32; 3];
+        }
+        let h = (v + l).normalize();
+        let theta_half = h.2.clamp(-1f32, 1f32).acos();
+        let phi_half = h.1.atan2(h.0);
+
+        // Rotates the light so that the half vector becomes the normal.
+        let rotate_z = |Vec3(x, y, z): Vec3, angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            Vec3(x * cos - y * sin, x * sin + y * cos, z)
+        };
+        let rotate_y = |Vec3(x, y, z): Vec3, angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            Vec3(x * cos + z * sin, y, -x * sin + z * cos)
+        };
+        let difference = rotate_y(rotate_z(l, -phi_half), -theta_half);
+        let theta_difference = difference.2.clamp(-1f32, 1f32).acos();
+        let mut phi_difference = difference.1.atan2(difference.0);
+        // Reciprocity makes the table symmetric under a half turn.
+        if phi_difference < 0f32 {
+            phi_difference += PI;
+        }
+
+        let theta_half_index = (((theta_half / FRAC_PI_2).max(0f32).sqrt()
+            * THETA_HALF_STEPS as f32) as usize)
+            .min(THETA_HALF_STEPS - 1);
+        let theta_difference_index =
+            ((theta_difference / FRAC_PI_2 * THETA_DIFFERENCE_STEPS as f32) as usize)
+                .min(THETA_DIFFERENCE_STEPS - 1);
+        let phi_difference_index = ((phi_difference / PI * PHI_DIFFERENCE_STEPS as f32) as usize)
+            .min(PHI_DIFFERENCE_STEPS - 1);
+        self.table[(theta_half_index * THETA_DIFFERENCE_STEPS + theta_difference_index)
+            * PHI_DIFFERENCE_STEPS
+            + phi_difference_index]
+    }
+
+    /// Density of the tabulated half vector distribution at `h`, per solid
+    /// angle.
+    fn half_vector_pdf(&self, h: Vec3) -> f32 {
+        let theta_half = h.2.clamp(-1f32, 1f32).acos();
+        let index = (((theta_half / FRAC_PI_2).max(0f32).sqrt() * THETA_HALF_STEPS as f32)
+            as usize)
+            .min(THETA_HALF_STEPS - 1);
+        let previous = if index == 0 {
+            0f32
+        } else {
+            self.theta_half_cdf[index - 1]
+        };
+        let (low, high) = theta_half_bin(index);
+        (self.theta_half_cdf[index] - previous) / (2f32 * PI * (low.cos() - high.cos()))
+    }
+}
+
+/// Range of half angles falling into bin `index`, whose width grows
+/// quadratically to resolve the specular peak.
+fn theta_half_bin(index: usize) -> (f32, f32) {
+    let bin = |i: usize| {
+        let t = i as f32 / THETA_HALF_STEPS as f32;
+        t * t * FRAC_PI_2
+    };
+    (bin(index), bin(index + 1))
+}
+
+impl Bsdf for MeasuredBrdf {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        let v = frame.to_local(surface_to_view);
+        let l = frame.to_local(surface_to_light);
+        let [r, g, b] = self.lookup(v, l);
+        HdrColor::new(r, g, b) * l.2.max(0f32)
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        let surface_to_light = if u_lobe < DIFFUSE_SAMPLING_PROBABILITY {
+            sample_cosine_hemisphere(frame.normal, u)
+        } else {
+            // The lobe variable is reused to choose the bin, then `u`
+            // places the half vector within it.
+            let u_bin =
+                (u_lobe - DIFFUSE_SAMPLING_PROBABILITY) / (1f32 - DIFFUSE_SAMPLING_PROBABILITY);
+            let index = self
+                .theta_half_cdf
+                .partition_point(|&cumulative| cumulative <= u_bin)
+                .min(THETA_HALF_STEPS - 1);
+            let (low, high) = theta_half_bin(index);
+            let cos_theta = low.cos() + (high.cos() - low.cos()) * u.0;
+            let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+            let phi = 2f32 * PI * u.1;
+            let h = frame.to_world(Vec3(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                cos_theta,
+            ));
+            Direction {
+                vec: h.vec * (2f32 * surface_to_view.cos_angle_between(h)) - surface_to_view.vec,
+            }
+        };
+        let pdf = self.pdf(surface_to_view, surface_to_light, frame);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            surface_to_light,
+            value: self.evaluate(surface_to_view, surface_to_light, frame),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        let v = frame.to_local(surface_to_view);
+        let l = frame.to_local(surface_to_light);
+        if v.2 <= 0f32 || l.2 <= 0f32 {
+            return 0f32;
+        }
+        let h = (v + l).normalize();
+        let half_vector_pdf = self.half_vector_pdf(h) / (4f32 * v.dot(h).abs());
+        DIFFUSE_SAMPLING_PROBABILITY * l.2 / PI
+            + (1f32 - DIFFUSE_SAMPLING_PROBABILITY) * half_vector_pdf
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        let cos_v = frame
+            .normal
+            .cos_angle_between(surface_to_view)
+            .clamp(0f32, 1f32);
+        self.albedo[((cos_v * ALBEDO_STEPS as f32) as usize).min(ALBEDO_STEPS - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bsdf::tests::assert_samples_follow_pdf;
+
+    /// A table in the MERL format holding a white Lambertian surface.
+    fn lambertian() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for dimension in [
+            THETA_HALF_STEPS,
+            THETA_DIFFERENCE_STEPS,
+            PHI_DIFFERENCE_STEPS,
+        ] {
+            bytes.extend_from_slice(&(dimension as i32).to_le_bytes());
+        }
+        for scale in CHANNEL_SCALES {
+            for _ in 0..TABLE_SIZE {
+                bytes.extend_from_slice(&(1f64 / std::f64::consts::PI / scale).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_measured_brdf_reads_lambertian_table() {
+        let brdf = MeasuredBrdf::read(&lambertian()[..]).unwrap();
+        assert!(MeasuredBrdf::read(&lambertian()[..100]).is_err());
+
+        let up = Direction {
+            vec: Vec3(0f32, 0f32, 1f32),
+        };
+        let frame = ShadingFrame::new(
+            up,
+            up,
+            Direction {
+                vec: Vec3(1f32, 0f32, 0f32),
+            },
+        );
+        let v = Direction {
+            vec: Vec3(0.6f32, 0f32, 0.8f32),
+        };
+        let l = Direction {
+            vec: Vec3(0f32, -0.28f32, 0.96f32),
+        };
+        let value = brdf.evaluate(v, l, frame);
+        assert!((value.b - 0.96f32 / PI).abs() < 0.001f32);
+        assert!((brdf.reflectance(v, frame).r - 1f32).abs() < 0.01f32);
+
+        assert_samples_follow_pdf(&brdf, v);
+    }
+}
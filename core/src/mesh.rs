@@ -0,0 +1,550 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    base_types::{BoundingBox, Direction, Position},
+    bsdf::local_to_world,
+    math::Vec3,
+    scene::{Intersection, Object, ObjectId, Ray},
+    texture::{Material, Texture, TextureContext},
+};
+
+/// Triangles a leaf of the hierarchy holds at most.
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+#[derive(Clone, Copy)]
+pub struct Vertex {
+    pub position: Position,
+    pub normal: Direction,
+    pub uv: (f32, f32),
+}
+
+enum Node {
+    Leaf {
+        bounds: BoundingBox,
+        start: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: BoundingBox,
+        /// The first child directly follows its parent.
+        second_child: usize,
+    },
+}
+
+/// Triangles sharing vertices, wound counter-clockwise when seen from
+/// outside, in a bounding volume hierarchy.
+pub struct TriangleMesh {
+    pub id: ObjectId,
+    pub material: Rc<dyn Material>,
+    // Private, as the hierarchy in `nodes` is built over them.
+    vertices: Vec<Vertex>,
+    triangles: Vec<[usize; 3]>,
+    nodes: Vec<Node>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        id: ObjectId,
+        vertices: Vec<Vertex>,
+        triangles: Vec<[usize; 3]>,
+        material: Rc<dyn Material>,
+    ) -> TriangleMesh {
+        let mut mesh = TriangleMesh {
+            id,
+            material,
+            vertices,
+            triangles,
+            nodes: Vec::new(),
+        };
+        let count = mesh.triangles.len();
+        if count > 0 {
+            mesh.build(0, count);
+        }
+        mesh
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    /// Indices into `vertices`, in an order chosen by the hierarchy.
+    pub fn triangles(&self) -> &[[usize; 3]] {
+        &self.triangles
+    }
+
+    fn triangle_bounds(&self, triangle: [usize; 3]) -> BoundingBox {
+        triangle
+            .iter()
+            .map(|&i| BoundingBox::from_position(self.vertices[i].position))
+            .reduce(BoundingBox::union)
+            .unwrap()
+    }
+
+    fn centroid(&self, triangle: [usize; 3]) -> Vec3 {
+        triangle.iter().fold(Vec3(0f32, 0f32, 0f32), |acc, &i| {
+            acc + self.vertices[i].position.vec
+        }) / 3f32
+    }
+
+    /// Splits the triangles in `start..start + count` at the median of the
+    /// longest axis of their centroids.
+    fn build(&mut self, start: usize, count: usize) -> usize {
+        let bounds = self.triangles[start..start + count]
+            .iter()
+            .map(|&triangle| self.triangle_bounds(triangle))
+            .reduce(BoundingBox::union)
+            .unwrap();
+        let index = self.nodes.len();
+        if count <= MAX_LEAF_TRIANGLES {
+            self.nodes.push(Node::Leaf {
+                bounds,
+                start,
+                count,
+            });
+            return index;
+        }
+
+        let Vec3(x, y, z) = bounds.diagonal().vec;
+        let axis = if x >= y && x >= z {
+            0
+        } else if y >= z {
+            1
+        } else {
+            2
+        };
+        let key = |mesh: &TriangleMesh, triangle: [usize; 3]| {
+            let Vec3(x, y, z) = mesh.centroid(triangle);
+            [x, y, z][axis]
+        };
+        let mut triangles = self.triangles[start..start + count].to_vec();
+        triangles.sort_by(|&a, &b| key(self, a).total_cmp(&key(self, b)));
+        self.triangles[start..start + count].copy_from_slice(&triangles);
+
+        self.nodes.push(Node::Interior {
+            bounds,
+            second_child: 0,
+        });
+        let half = count / 2;
+        self.build(start, half);
+        let second = self.build(start + half, count - half);
+        if let Node::Interior { second_child, .. } = &mut self.nodes[index] {
+            *second_child = second;
+        }
+        index
+    }
+
+    /// Möller and Trumbore's test, returning the distance along the ray and
+    /// the barycentric coordinates of the second and third vertices.
+    fn intersect_triangle(
+        &self,
+        triangle: [usize; 3],
+        origin: Vec3,
+        direction: Vec3,
+    ) -> Option<(f32, f32, f32)> {
+        let [a, b, c] = triangle.map(|i| self.vertices[i].position.vec);
+        let (edge1, edge2) = (b - a, c - a);
+        let p = direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < 1e-12f32 {
+            return None;
+        }
+        let inverse = 1f32 / determinant;
+        let s = origin - a;
+        let u = s.dot(p) * inverse;
+        if !(0f32..=1f32).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = direction.dot(q) * inverse;
+        if v < 0f32 || u + v > 1f32 {
+            return None;
+        }
+        let t = edge2.dot(q) * inverse;
+        (t >= 0f32).then_some((t, u, v))
+    }
+
+    fn nearest(&self, origin: Vec3, direction: Vec3) -> Option<(usize, f32, f32, f32)> {
+        let inverse = Vec3(1f32 / direction.0, 1f32 / direction.1, 1f32 / direction.2);
+        let mut nearest: Option<(usize, f32, f32, f32)> = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let limit = nearest.map_or(f32::INFINITY, |(_, t, _, _)| t);
+            let bounds = match self.nodes[index] {
+                Node::Leaf { bounds, .. } | Node::Interior { bounds, .. } => bounds,
+            };
+            if !hits_box(bounds, origin, inverse, limit) {
+                continue;
+            }
+            match self.nodes[index] {
+                Node::Leaf { start, count, .. } => {
+                    for i in start..start + count {
+                        if let Some((t, u, v)) =
+                            self.intersect_triangle(self.triangles[i], origin, direction)
+                        {
+                            if t < nearest.map_or(f32::INFINITY, |(_, t, _, _)| t) {
+                                nearest = Some((i, t, u, v));
+                            }
+                        }
+                    }
+                }
+                Node::Interior { second_child, .. } => {
+                    stack.push(second_child);
+                    stack.push(index + 1);
+                }
+            }
+        }
+        nearest
+    }
+}
+
+/// The slab test between a ray and a box, up to distance `limit`.
+fn hits_box(bounds: BoundingBox, origin: Vec3, inverse_direction: Vec3, limit: f32) -> bool {
+    let (min, max) = (bounds.min.vec, bounds.max.vec);
+    let mut near = 0f32;
+    let mut far = limit;
+    for (min, max, origin, inverse) in [
+        (min.0, max.0, origin.0, inverse_direction.0),
+        (min.1, max.1, origin.1, inverse_direction.1),
+        (min.2, max.2, origin.2, inverse_direction.2),
+    ] {
+        // A ray parallel to the slab either stays inside it or never meets it.
+        if inverse.is_infinite() {
+            if origin < min || origin > max {
+                return false;
+            }
+            continue;
+        }
+        let (t0, t1) = ((min - origin) * inverse, (max - origin) * inverse);
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+        if near > far {
+            return false;
+        }
+    }
+    true
+}
+
+impl Object for TriangleMesh {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let (index, t, b1, b2) = self.nearest(ray.origin.vec, ray.direction.vec)?;
+        let triangle = self.triangles[index];
+        let [a, b, c] = triangle.map(|i| self.vertices[i]);
+        let b0 = 1f32 - b1 - b2;
+        let position = ray.origin + ray.direction * t;
+        let real_normal = Direction {
+            vec: (b.position - a.position)
+                .vec
+                .cross((c.position - a.position).vec)
+                .normalize(),
+        };
+        let normal = Direction {
+            vec: (a.normal.vec * b0 + b.normal.vec * b1 + c.normal.vec * b2).normalize(),
+        };
+        let interpolate = |[x, y, z]: [f32; 3]| x * b0 + y * b1 + z * b2;
+        let uv = (
+            interpolate([a.uv.0, b.uv.0, c.uv.0]),
+            interpolate([a.uv.1, b.uv.1, c.uv.1]),
+        );
+
+        // Solving p = a + (b - a) u' + (c - a) v' in UV space gives the
+        // direction of increasing u.
+        let (du1, dv1) = (b.uv.0 - a.uv.0, b.uv.1 - a.uv.1);
+        let (du2, dv2) = (c.uv.0 - a.uv.0, c.uv.1 - a.uv.1);
+        let determinant = du1 * dv2 - du2 * dv1;
+        let dp_du = ((b.position - a.position).vec * dv2 - (c.position - a.position).vec * dv1)
+            / determinant;
+        let tangent = if determinant.abs() > 1e-12f32 && dp_du.length_squared() > 0f32 {
+            Direction {
+                vec: dp_du.normalize(),
+            }
+        } else {
+            local_to_world(normal, Vec3(1f32, 0f32, 0f32))
+        };
+
+        // UVs at the points where the neighbouring rays meet the triangle's
+        // plane.
+        let (duv_dx, duv_dy) = match ray.differentials {
+            Some(differentials) => {
+                let uv_at = |origin: Position, direction: Direction| {
+                    let denominator = direction.vec.dot(real_normal.vec);
+                    let distance = (a.position - origin).vec.dot(real_normal.vec) / denominator;
+                    let p = (origin + direction * distance - a.position).vec;
+                    let (edge1, edge2) =
+                        ((b.position - a.position).vec, (c.position - a.position).vec);
+                    let (d11, d12, d22) = (edge1.dot(edge1), edge1.dot(edge2), edge2.dot(edge2));
+                    let (p1, p2) = (p.dot(edge1), p.dot(edge2));
+                    let inverse = 1f32 / (d11 * d22 - d12 * d12);
+                    let e1 = (d22 * p1 - d12 * p2) * inverse;
+                    let e2 = (d11 * p2 - d12 * p1) * inverse;
+                    (
+                        a.uv.0 + du1 * e1 + du2 * e2 - uv.0,
+                        a.uv.1 + dv1 * e1 + dv2 * e2 - uv.1,
+                    )
+                };
+                (
+                    uv_at(differentials.x_origin, differentials.x_direction),
+                    uv_at(differentials.y_origin, differentials.y_direction),
+                )
+            }
+            None => ((0f32, 0f32), (0f32, 0f32)),
+        };
+
+        let context = TextureContext {
+            position,
+            uv,
+            duv_dx,
+            duv_dy,
+            normal,
+        };
+        Some(Intersection {
+            object_id: self.id,
+            position,
+            real_normal,
+            tangent,
+            context,
+            material: self.material.clone(),
+        })
+    }
+}
+
+/// Edges are never split below this length, whatever the tessellation asks
+/// for, so that a camera on the surface cannot split them forever.
+const MIN_EDGE_LENGTH: f32 = 1e-4f32;
+
+/// How many times a triangle of the original mesh may be split, as a last
+/// stop for tessellations with NaN parameters.
+const MAX_SUBDIVISION_DEPTH: usize = 16;
+
+/// Controls how finely `displace` subdivides a mesh.
+pub struct Tessellation {
+    /// Where the mesh is seen from.
+    pub camera_position: Position,
+    /// How many pixels an edge one unit long spans one unit in front of the
+    /// camera, that is the image width over the width of the view at unit
+    /// distance.
+    pub pixels_per_unit: f32,
+    /// Edges longer than this on screen are split.
+    pub max_edge_pixels: f32,
+    /// Edges shorter than this are never split, however close they are.
+    pub min_edge_length: f32,
+}
+
+impl Tessellation {
+    fn splits(&self, a: Vertex, b: Vertex) -> bool {
+        let length = (b.position - a.position).distance();
+        let midpoint = a.position + (b.position - a.position) * 0.5f32;
+        let distance = (midpoint - self.camera_position).distance().max(1e-6f32);
+        length > self.min_edge_length.max(MIN_EDGE_LENGTH)
+            && length / distance * self.pixels_per_unit > self.max_edge_pixels
+    }
+}
+
+/// A new mesh whose triangles are subdivided until their edges are small on
+/// screen, then moved along their normals by `scale` times `height`. As
+/// whether an edge is split only depends on its two ends, neighbouring
+/// triangles agree and no cracks open.
+pub fn displace(
+    mesh: &TriangleMesh,
+    height: &dyn Texture<f32>,
+    scale: f32,
+    tessellation: &Tessellation,
+) -> TriangleMesh {
+    let mut vertices = mesh.vertices().to_vec();
+    let mut midpoints = HashMap::new();
+    let mut triangles = Vec::new();
+    for &triangle in mesh.triangles() {
+        subdivide(
+            triangle,
+            &mut vertices,
+            &mut midpoints,
+            &mut triangles,
+            tessellation,
+            0,
+        );
+    }
+
+    let displaced: Vec<Vertex> = vertices
+        .iter()
+        .map(|vertex| {
+            let h = height.evaluate(&TextureContext {
+                position: vertex.position,
+                uv: vertex.uv,
+                duv_dx: (0f32, 0f32),
+                duv_dy: (0f32, 0f32),
+                normal: vertex.normal,
+            });
+            Vertex {
+                position: vertex.position + vertex.normal * (h * scale),
+                ..*vertex
+            }
+        })
+        .collect();
+
+    // The displaced surface is shaded with normals averaged from its faces,
+    // weighted by their areas.
+    let mut normals = vec![Vec3(0f32, 0f32, 0f32); displaced.len()];
+    for &[a, b, c] in &triangles {
+        let face = (displaced[b].position - displaced[a].position)
+            .vec
+            .cross((displaced[c].position - displaced[a].position).vec);
+        for i in [a, b, c] {
+            normals[i] = normals[i] + face;
+        }
+    }
+    let vertices = displaced
+        .into_iter()
+        .zip(normals)
+        .map(|(vertex, normal)| Vertex {
+            normal: if normal.length_squared() > 0f32 {
+                Direction {
+                    vec: normal.normalize(),
+                }
+            } else {
+                vertex.normal
+            },
+            ..vertex
+        })
+        .collect();
+
+    TriangleMesh::new(mesh.id, vertices, triangles, mesh.material.clone())
+}
+
+fn subdivide(
+    [a, b, c]: [usize; 3],
+    vertices: &mut Vec<Vertex>,
+    midpoints: &mut HashMap<(usize, usize), usize>,
+    triangles: &mut Vec<[usize; 3]>,
+    tessellation: &Tessellation,
+    depth: usize,
+) {
+    if depth == MAX_SUBDIVISION_DEPTH {
+        triangles.push([a, b, c]);
+        return;
+    }
+    let mut midpoint = |i: usize, j: usize, vertices: &mut Vec<Vertex>| {
+        if !tessellation.splits(vertices[i], vertices[j]) {
+            return None;
+        }
+        let key = (i.min(j), i.max(j));
+        Some(*midpoints.entry(key).or_insert_with(|| {
+            let (p, q) = (vertices[i], vertices[j]);
+            vertices.push(Vertex {
+                position: p.position + (q.position - p.position) * 0.5f32,
+                normal: Direction {
+                    vec: (p.normal.vec + q.normal.vec).normalize(),
+                },
+                uv: ((p.uv.0 + q.uv.0) * 0.5f32, (p.uv.1 + q.uv.1) * 0.5f32),
+            });
+            vertices.len() - 1
+        }))
+    };
+    let ab = midpoint(a, b, vertices);
+    let bc = midpoint(b, c, vertices);
+    let ca = midpoint(c, a, vertices);
+
+    let children: Vec<[usize; 3]> = match (ab, bc, ca) {
+        (None, None, None) => {
+            triangles.push([a, b, c]);
+            return;
+        }
+        (Some(ab), Some(bc), Some(ca)) => {
+            vec![[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+        }
+        (Some(ab), None, None) => vec![[a, ab, c], [ab, b, c]],
+        (None, Some(bc), None) => vec![[a, b, bc], [a, bc, c]],
+        (None, None, Some(ca)) => vec![[a, b, ca], [ca, b, c]],
+        (Some(ab), Some(bc), None) => vec![[a, ab, c], [ab, b, bc], [ab, bc, c]],
+        (None, Some(bc), Some(ca)) => vec![[a, b, ca], [ca, b, bc], [ca, bc, c]],
+        (Some(ab), None, Some(ca)) => vec![[a, ab, ca], [ab, b, c], [ca, ab, c]],
+    };
+    for child in children {
+        subdivide(
+            child,
+            vertices,
+            midpoints,
+            triangles,
+            tessellation,
+            depth + 1,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base_types::LdrColor, bsdf::CookTorrance, bsdf::F0_NORMAL, texture::Constant};
+
+    #[test]
+    fn test_displaced_mesh_is_hit_where_displaced() {
+        let up = Direction {
+            vec: Vec3(0f32, 0f32, 1f32),
+        };
+        let vertex = |x: f32, y: f32| Vertex {
+            position: Position::new(x, y, 0f32),
+            normal: up,
+            uv: (x, y),
+        };
+        let mesh = TriangleMesh::new(
+            ObjectId(0),
+            vec![
+                vertex(-1f32, -1f32),
+                vertex(1f32, -1f32),
+                vertex(1f32, 1f32),
+                vertex(-1f32, 1f32),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            Rc::new(CookTorrance {
+                albedo: LdrColor::new(0.5f32, 0.5f32, 0.5f32),
+                roughness_u: 0.5f32,
+                roughness_v: 0.5f32,
+                f0: F0_NORMAL,
+                thin_film: None,
+            }),
+        );
+        let ray = |x: f32, y: f32| Ray {
+            origin: Position::new(x, y, 5f32),
+            direction: -up,
+            differentials: None,
+        };
+        let hit = mesh.intersect(&ray(0.3f32, -0.5f32)).unwrap();
+        assert!(hit.position.vec.2.abs() < 0.00042f32);
+        assert!(hit.real_normal.cos_angle_between(up) > 0.9999f32);
+        assert!(mesh.intersect(&ray(1.5f32, 0f32)).is_none());
+
+        let displaced = displace(
+            &mesh,
+            &Constant(1f32),
+            0.5f32,
+            &Tessellation {
+                camera_position: Position::new(0f32, 0f32, 5f32),
+                pixels_per_unit: 100f32,
+                max_edge_pixels: 10f32,
+                min_edge_length: 0.01f32,
+            },
+        );
+        assert!(displaced.triangles().len() > 32);
+        for &(x, y) in &[(0.3f32, -0.5f32), (-0.9f32, 0.8f32), (0f32, 0f32)] {
+            let hit = displaced.intersect(&ray(x, y)).unwrap();
+            assert!((hit.position.vec.2 - 0.5f32).abs() < 0.00042f32);
+            assert!(hit.shading_normal().cos_angle_between(up) > 0.999f32);
+        }
+
+        // A camera on the surface with no minimum edge length still stops.
+        let close = displace(
+            &mesh,
+            &Constant(0f32),
+            1f32,
+            &Tessellation {
+                camera_position: Position::new(0.3f32, 0.2f32, 0f32),
+                pixels_per_unit: 100f32,
+                max_edge_pixels: 10f32,
+                min_edge_length: 0f32,
+            },
+        );
+        assert!(close.triangles().len() > displaced.triangles().len());
+        assert!(close.intersect(&ray(0.3f32, 0.2f32)).is_some());
+    }
+}
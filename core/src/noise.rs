@@ -0,0 +1,295 @@
+use crate::{
+    base_types::{Position, Transform},
+    math::Vec3,
+    texture::{Interpolate, Texture, TextureContext},
+};
+
+/// Mixes lattice coordinates into 32 pseudo-random bits.
+fn hash(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed
+        ^ (x as u32).wrapping_mul(0x8da6b343)
+        ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^ (h >> 16)
+}
+
+/// One of the twelve edge directions of a cube, as in Perlin's improved
+/// noise, dotted with `(x, y, z)`.
+fn gradient(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+/// Perlin's improved gradient noise, roughly in [-1, 1] and zero at every
+/// integer lattice point.
+pub fn perlin(p: Vec3) -> f32 {
+    let fade = |t: f32| t * t * t * (t * (t * 6f32 - 15f32) + 10f32);
+    let Vec3(x, y, z) = p;
+    let (xi, yi, zi) = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+    let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        gradient(
+            hash(xi + dx, yi + dy, zi + dz, 0),
+            x - dx as f32,
+            y - dy as f32,
+            z - dz as f32,
+        )
+    };
+    let x00 = corner(0, 0, 0).lerp(corner(1, 0, 0), u);
+    let x10 = corner(0, 1, 0).lerp(corner(1, 1, 0), u);
+    let x01 = corner(0, 0, 1).lerp(corner(1, 0, 1), u);
+    let x11 = corner(0, 1, 1).lerp(corner(1, 1, 1), u);
+    x00.lerp(x10, v).lerp(x01.lerp(x11, v), w)
+}
+
+/// Perlin's simplex noise, roughly in [-1, 1], following Gustavson's
+/// "Simplex noise demystified".
+pub fn simplex(p: Vec3) -> f32 {
+    const SKEW: f32 = 1f32 / 3f32;
+    const UNSKEW: f32 = 1f32 / 6f32;
+    let Vec3(x, y, z) = p;
+    let s = (x + y + z) * SKEW;
+    let (i, j, k) = ((x + s).floor(), (y + s).floor(), (z + s).floor());
+    let t = (i + j + k) * UNSKEW;
+    let (x0, y0, z0) = (x - (i - t), y - (j - t), z - (k - t));
+
+    // The simplex containing the point is found from the order of its
+    // coordinates within the skewed cube.
+    let (first, second) = if x0 >= y0 {
+        if y0 >= z0 {
+            ((1, 0, 0), (1, 1, 0))
+        } else if x0 >= z0 {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else if y0 < z0 {
+        ((0, 0, 1), (0, 1, 1))
+    } else if x0 < z0 {
+        ((0, 1, 0), (0, 1, 1))
+    } else {
+        ((0, 1, 0), (1, 1, 0))
+    };
+
+    let (i, j, k) = (i as i32, j as i32, k as i32);
+    [(0, 0, 0), first, second, (1, 1, 1)]
+        .iter()
+        .enumerate()
+        .map(|(n, &(di, dj, dk))| {
+            let offset = n as f32 * UNSKEW;
+            let (dx, dy, dz) = (
+                x0 - di as f32 + offset,
+                y0 - dj as f32 + offset,
+                z0 - dk as f32 + offset,
+            );
+            let falloff = 0.6f32 - dx * dx - dy * dy - dz * dz;
+            if falloff <= 0f32 {
+                0f32
+            } else {
+                let falloff = falloff * falloff;
+                falloff * falloff * gradient(hash(i + di, j + dj, k + dk, 1), dx, dy, dz)
+            }
+        })
+        .sum::<f32>()
+        * 32f32
+}
+
+/// Worley's cellular noise: the distances to the nearest and second nearest
+/// of a set of points scattered one per unit cube.
+pub fn worley(p: Vec3) -> (f32, f32) {
+    let Vec3(x, y, z) = p;
+    let (xi, yi, zi) = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+    let mut nearest = (f32::INFINITY, f32::INFINITY);
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (cx, cy, cz) = (xi + dx, yi + dy, zi + dz);
+                let h = hash(cx, cy, cz, 2);
+                let unit = |bits: u32| (bits & 0x3ff) as f32 / 1024f32;
+                let feature = Vec3(
+                    cx as f32 + unit(h),
+                    cy as f32 + unit(h >> 10),
+                    cz as f32 + unit(h >> 20),
+                );
+                let difference = feature - p;
+                let distance = difference.dot(difference).sqrt();
+                if distance < nearest.0 {
+                    nearest = (distance, nearest.0);
+                } else if distance < nearest.1 {
+                    nearest.1 = distance;
+                }
+            }
+        }
+    }
+    nearest
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Noise {
+    Perlin,
+    Simplex,
+    /// Distance to the nearest cell point, rescaled to roughly [-1, 1].
+    Worley,
+}
+
+impl Noise {
+    pub fn evaluate(self, p: Vec3) -> f32 {
+        match self {
+            Noise::Perlin => perlin(p),
+            Noise::Simplex => simplex(p),
+            Noise::Worley => worley(p).0 * 2f32 - 1f32,
+        }
+    }
+}
+
+/// Fractional Brownian motion: octaves of `noise`, each `lacunarity` times
+/// the frequency and `gain` times the amplitude of the previous one.
+#[derive(Clone, Copy)]
+pub struct Fbm {
+    pub noise: Noise,
+    pub octaves: usize,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+impl Fbm {
+    /// Normalised by the total amplitude, so roughly in [-1, 1].
+    pub fn evaluate(&self, p: Vec3) -> f32 {
+        self.sum(p, |value| value)
+    }
+
+    /// The sum of absolute values, in [0, 1], whose creases look like
+    /// turbulent flow.
+    pub fn turbulence(&self, p: Vec3) -> f32 {
+        self.sum(p, f32::abs)
+    }
+
+    fn sum(&self, p: Vec3, shape: impl Fn(f32) -> f32) -> f32 {
+        let mut total = 0f32;
+        let mut amplitude = 1f32;
+        let mut frequency = 1f32;
+        let mut normalization = 0f32;
+        for _ in 0..self.octaves {
+            total += amplitude * shape(self.noise.evaluate(p * frequency));
+            normalization += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        if normalization > 0f32 {
+            total / normalization
+        } else {
+            0f32
+        }
+    }
+}
+
+/// Where a solid texture is evaluated: `transform` takes world positions
+/// into texture space, so the inverse of an object's transform makes the
+/// texture stick to the object.
+fn texture_space(context: &TextureContext, transform: Transform) -> Vec3 {
+    let Position { vec } = context.position.apply(transform);
+    vec
+}
+
+/// Blends from `from` to `to` by fractal noise.
+pub struct NoiseTexture<T> {
+    pub fbm: Fbm,
+    pub transform: Transform,
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: Interpolate> Texture<T> for NoiseTexture<T> {
+    fn evaluate(&self, context: &TextureContext) -> T {
+        let value = self.fbm.evaluate(texture_space(context, self.transform));
+        self.from
+            .lerp(self.to, (value * 0.5f32 + 0.5f32).clamp(0f32, 1f32))
+    }
+}
+
+/// Veins along x, bent by turbulence of strength `distortion`.
+pub struct Marble<T> {
+    pub fbm: Fbm,
+    pub transform: Transform,
+    pub distortion: f32,
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: Interpolate> Texture<T> for Marble<T> {
+    fn evaluate(&self, context: &TextureContext) -> T {
+        let p = texture_space(context, self.transform);
+        let phase = p.0 + self.distortion * self.fbm.turbulence(p);
+        self.from.lerp(
+            self.to,
+            0.5f32 + 0.5f32 * (phase * std::f32::consts::PI).sin(),
+        )
+    }
+}
+
+/// Growth rings around the z axis, one per unit of radius, wobbled by noise
+/// of strength `distortion`.
+pub struct Wood<T> {
+    pub fbm: Fbm,
+    pub transform: Transform,
+    pub distortion: f32,
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: Interpolate> Texture<T> for Wood<T> {
+    fn evaluate(&self, context: &TextureContext) -> T {
+        let p = texture_space(context, self.transform);
+        let radius = (p.0 * p.0 + p.1 * p.1).sqrt() + self.distortion * self.fbm.evaluate(p);
+        self.from.lerp(self.to, radius - radius.floor())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_noise_is_bounded_and_continuous() {
+        let mut rng = StdRng::seed_from_u64(42);
+        assert_eq!(perlin(Vec3(3f32, -2f32, 7f32)), 0f32);
+
+        for noise in [Noise::Perlin, Noise::Simplex, Noise::Worley] {
+            for _ in 0..420 {
+                let p = Vec3(
+                    rng.gen_range(-100.0..100.0),
+                    rng.gen_range(-100.0..100.0),
+                    rng.gen_range(-100.0..100.0),
+                );
+                let value = noise.evaluate(p);
+                assert!((-1.1f32..=1.1f32).contains(&value));
+                let nearby = noise.evaluate(p + Vec3(0.001f32, 0.001f32, 0.001f32));
+                assert!((value - nearby).abs() < 0.05f32);
+            }
+        }
+
+        let (first, second) = worley(Vec3(0.5f32, 0.5f32, 0.5f32));
+        assert!(first <= second);
+    }
+}
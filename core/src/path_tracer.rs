@@ -0,0 +1,205 @@
+use crate::{
+    base_types::HdrColor,
+    integrator::Integrator,
+    sampler::Sampler,
+    scene::{Ray, Scene},
+    Heuristic, ShadingPoint,
+};
+
+/// Estimates global illumination by following random paths from the camera,
+/// sampling the BSDF at every bounce and the lights at every hit.
+pub struct PathTracer {
+    /// Bounces after which a path is cut short.
+    pub max_depth: usize,
+    /// Bounces after which paths are terminated at random, in proportion to
+    /// how little they still carry.
+    pub roulette_depth: usize,
+    /// How direct light found by sampling lights and the BSDF is combined.
+    pub heuristic: Heuristic,
+}
+
+impl PathTracer {
+    pub fn new() -> PathTracer {
+        PathTracer {
+            max_depth: 64,
+            roulette_depth: 3,
+            heuristic: Heuristic::Power,
+        }
+    }
+}
+
+impl Default for PathTracer {
+    fn default() -> PathTracer {
+        PathTracer::new()
+    }
+}
+
+impl Integrator for PathTracer {
+    fn li(&self, scene: &Scene, ray: &Ray, sampler: &mut Sampler) -> Option<HdrColor> {
+        let mut ray = *ray;
+        let mut radiance = HdrColor::new(0f32, 0f32, 0f32);
+        let mut throughput = HdrColor::new(1f32, 1f32, 1f32);
+        for depth in 0..=self.max_depth {
+            let Some(intersection) = scene.world.intersect(&ray) else {
+                if depth == 0 {
+                    return scene.environment.background(ray.direction);
+                }
+                radiance = radiance + throughput * scene.environment.radiance(ray.direction);
+                break;
+            };
+            let point = ShadingPoint::new(intersection, -ray.direction);
+            // Emissive surfaces are not among the lights, so they are only
+            // found by hitting them. Light scattered below the surface is
+            // gathered by random walks from the hit, as in Whitted.
+            radiance = radiance
+                + throughput
+                    * (point.bsdf.emission()
+                        + point.direct_light(scene, self.heuristic, sampler)
+                        + point.subsurface_light(scene, sampler));
+            if depth == self.max_depth {
+                break;
+            }
+
+            let Some(sample) = point.bsdf.sample(
+                point.surface_to_view,
+                point.frame,
+                sampler.next_1d(),
+                sampler.next_2d(),
+            ) else {
+                break;
+            };
+            throughput = throughput * sample.value * (1f32 / sample.pdf);
+            if depth >= self.roulette_depth {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(1f32);
+                if sampler.next_1d() >= survival {
+                    break;
+                }
+                throughput = throughput * (1f32 / survival);
+            }
+            ray = Ray {
+                origin: point.origin_towards(sample.surface_to_light),
+                direction: sample.surface_to_light,
+                differentials: None,
+            };
+        }
+        Some(radiance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use crate::{
+        base_types::{LdrColor, Position, Transform},
+        bsdf::{CookTorrance, Emissive, Subsurface},
+        render,
+        scene::{Object, ObjectId, PerspectiveCamera, Plane},
+    };
+
+    #[test]
+    fn test_light_bounces_between_planes() {
+        let grey = CookTorrance {
+            albedo: LdrColor::new(0.5f32, 0.5f32, 0.5f32),
+            roughness_u: 0.5f32,
+            roughness_v: 0.5f32,
+            f0: LdrColor::new(0f32, 0f32, 0f32),
+            thin_film: None,
+        };
+        let world: Vec<Box<dyn Object>> = vec![
+            Box::new(Plane::new(
+                ObjectId(0),
+                Transform::I,
+                1f32,
+                0f32,
+                0f32,
+                1f32,
+                Rc::new(grey),
+            )),
+            Box::new(Plane::new(
+                ObjectId(1),
+                Transform::I,
+                1f32,
+                0f32,
+                0f32,
+                -1f32,
+                Rc::new(Emissive {
+                    base: grey,
+                    emission: HdrColor::new(1f32, 1f32, 1f32),
+                }),
+            )),
+        ];
+        let scene = Scene::new(
+            Box::new(PerspectiveCamera::by_x(
+                Position::new(0f32, 0f32, 0f32),
+                0f32,
+                0f32,
+                0f32,
+                1f32,
+                1f32,
+            )),
+            Box::new(world),
+            Box::new(HdrColor::new(0f32, 0f32, 0f32)),
+            vec![],
+        );
+
+        // Between two infinite planes of albedo a, one glowing with E, the
+        // other receives E + a L and sends back L = a (E + a L).
+        let expected = 0.5f32 / (1f32 - 0.5f32 * 0.5f32);
+        let floor = render(
+            &scene,
+            &PathTracer::new(),
+            (0.5f32, 0.2f32),
+            (0.01f32, 0.01f32),
+            1024,
+            &mut Sampler::new(42),
+        )
+        .unwrap();
+        assert!((floor.g - expected).abs() < 0.05f32);
+    }
+
+    #[test]
+    fn test_subsurface_returns_light() {
+        let scene = Scene::new(
+            Box::new(PerspectiveCamera::by_x(
+                Position::new(0f32, 0f32, 0f32),
+                0f32,
+                0f32,
+                0f32,
+                1f32,
+                1f32,
+            )),
+            Box::new(Plane::new(
+                ObjectId(0),
+                Transform::I,
+                1f32,
+                0f32,
+                0f32,
+                1f32,
+                Rc::new(Subsurface {
+                    color: LdrColor::new(1f32, 1f32, 1f32),
+                    mean_free_path: HdrColor::new(0.1f32, 0.05f32, 0.02f32),
+                    ior: 1.4f32,
+                    roughness: 0.5f32,
+                }),
+            )),
+            Box::new(HdrColor::new(1f32, 1f32, 1f32)),
+            vec![],
+        );
+
+        // A white slab under a white sky sends back all the light it gets,
+        // most of it from below its surface.
+        let floor = render(
+            &scene,
+            &PathTracer::new(),
+            (0.5f32, 0.2f32),
+            (0.01f32, 0.01f32),
+            64,
+            &mut Sampler::new(42),
+        )
+        .unwrap();
+        assert!((floor.g - 1f32).abs() < 0.15f32);
+    }
+}
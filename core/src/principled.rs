@@ -0,0 +1,250 @@
+use crate::{
+    base_types::{Direction, HdrColor, LdrColor},
+    bsdf::{
+        Bsdf, BsdfSample, ClearCoat, CookTorrance, Mix, RoughDielectric, ShadingFrame, Sheen,
+        ThinFilm,
+    },
+};
+
+/// The parameters artists author in Disney's principled shading model, each
+/// from 0 to 1 unless noted otherwise.
+#[derive(Clone, Copy)]
+pub struct PrincipledParameters {
+    pub base_color: LdrColor,
+    pub metallic: f32,
+    /// Reflectance of dielectrics at normal incidence, where 0.5 is 4%.
+    pub specular: f32,
+    pub roughness: f32,
+    /// Stretches highlights along the surface tangent.
+    pub anisotropic: f32,
+    /// How much of the light entering a dielectric passes through it.
+    pub transmission: f32,
+    pub sheen: f32,
+    /// Tints the sheen towards the base colour.
+    pub sheen_tint: f32,
+    pub sheen_roughness: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    /// Radiance given off by the surface, unbounded.
+    pub emission: HdrColor,
+    /// An iridescent film over the base layer.
+    pub thin_film: Option<ThinFilm>,
+}
+
+impl Default for PrincipledParameters {
+    fn default() -> PrincipledParameters {
+        PrincipledParameters {
+            base_color: LdrColor::new(0.8f32, 0.8f32, 0.8f32),
+            metallic: 0f32,
+            specular: 0.5f32,
+            roughness: 0.5f32,
+            anisotropic: 0f32,
+            transmission: 0f32,
+            sheen: 0f32,
+            sheen_tint: 0.5f32,
+            sheen_roughness: 0.5f32,
+            clearcoat: 0f32,
+            clearcoat_roughness: 0.03f32,
+            emission: HdrColor::new(0f32, 0f32, 0f32),
+            thin_film: None,
+        }
+    }
+}
+
+/// Microfacet lobes need a little roughness to stay finite. This is still
+/// below `MIRROR_ROUGHNESS`, so polished surfaces keep mirroring the scene.
+const MIN_ROUGHNESS: f32 = 0.02f32;
+
+/// A material built from principled parameters, stacking clear coat and
+/// sheen over a blend of an opaque surface and a transmissive one.
+#[derive(Clone)]
+pub struct Principled {
+    lobes: ClearCoat<Sheen<Mix<CookTorrance, RoughDielectric>>>,
+    emission: HdrColor,
+}
+
+impl Principled {
+    pub fn new(parameters: PrincipledParameters) -> Principled {
+        let PrincipledParameters {
+            base_color,
+            metallic,
+            specular,
+            roughness,
+            anisotropic,
+            transmission,
+            sheen,
+            sheen_tint,
+            sheen_roughness,
+            clearcoat,
+            clearcoat_roughness,
+            emission,
+            thin_film,
+        } = parameters;
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let roughness = roughness.max(MIN_ROUGHNESS);
+        let clearcoat_roughness = clearcoat_roughness.max(MIN_ROUGHNESS);
+
+        let dielectric_f0 = 0.08f32 * specular;
+        let f0 = LdrColor::new(
+            lerp(dielectric_f0, base_color.r, metallic),
+            lerp(dielectric_f0, base_color.g, metallic),
+            lerp(dielectric_f0, base_color.b, metallic),
+        );
+        let aspect = (1f32 - 0.9f32 * anisotropic).sqrt().sqrt();
+        let opaque = CookTorrance {
+            albedo: LdrColor::new(
+                base_color.r * (1f32 - metallic),
+                base_color.g * (1f32 - metallic),
+                base_color.b * (1f32 - metallic),
+            ),
+            roughness_u: (roughness / aspect).min(1f32),
+            roughness_v: roughness * aspect,
+            f0,
+            thin_film,
+        };
+        let transmissive = RoughDielectric {
+            ior: 2f32 / (1f32 - dielectric_f0.sqrt()) - 1f32,
+            roughness,
+            transmittance: base_color,
+        };
+
+        let luminance = HdrColor::from(base_color).luminance();
+        let tint = if luminance > 0f32 {
+            base_color * (1f32 / luminance)
+        } else {
+            HdrColor::new(1f32, 1f32, 1f32)
+        };
+        let sheen_color = LdrColor::new(
+            lerp(1f32, tint.r, sheen_tint) * sheen,
+            lerp(1f32, tint.g, sheen_tint) * sheen,
+            lerp(1f32, tint.b, sheen_tint) * sheen,
+        );
+
+        Principled {
+            lobes: ClearCoat {
+                base: Sheen::new(
+                    Mix {
+                        a: opaque,
+                        b: transmissive,
+                        weight: transmission * (1f32 - metallic),
+                    },
+                    sheen_color,
+                    sheen_roughness,
+                ),
+                roughness: clearcoat_roughness,
+                weight: clearcoat,
+            },
+            emission,
+        }
+    }
+}
+
+impl Bsdf for Principled {
+    fn evaluate(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> HdrColor {
+        self.lobes
+            .evaluate(surface_to_view, surface_to_light, frame)
+    }
+
+    fn sample(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+        u_lobe: f32,
+        u: (f32, f32),
+    ) -> Option<BsdfSample> {
+        self.lobes.sample(surface_to_view, frame, u_lobe, u)
+    }
+
+    fn pdf(
+        &self,
+        surface_to_view: Direction,
+        surface_to_light: Direction,
+        frame: ShadingFrame,
+    ) -> f32 {
+        self.lobes.pdf(surface_to_view, surface_to_light, frame)
+    }
+
+    fn reflectance(&self, surface_to_view: Direction, frame: ShadingFrame) -> HdrColor {
+        self.lobes.reflectance(surface_to_view, frame)
+    }
+
+    fn transmits(&self) -> bool {
+        self.lobes.transmits()
+    }
+
+    fn specular_lobes(
+        &self,
+        surface_to_view: Direction,
+        frame: ShadingFrame,
+    ) -> Vec<(Direction, HdrColor)> {
+        self.lobes.specular_lobes(surface_to_view, frame)
+    }
+
+    fn emission(&self) -> HdrColor {
+        self.emission
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3;
+
+    #[test]
+    fn test_principled_metal_is_cook_torrance() {
+        let base_color = LdrColor::new(1f32, 0.766f32, 0.336f32);
+        let principled = Principled::new(PrincipledParameters {
+            base_color,
+            metallic: 1f32,
+            roughness: 0.4f32,
+            ..Default::default()
+        });
+        let metal = CookTorrance {
+            albedo: LdrColor::new(0f32, 0f32, 0f32),
+            roughness_u: 0.4f32,
+            roughness_v: 0.4f32,
+            f0: base_color,
+            thin_film: None,
+        };
+        assert!(!principled.transmits());
+
+        let up = Direction {
+            vec: Vec3(0f32, 0f32, 1f32),
+        };
+        let frame = ShadingFrame::new(
+            up,
+            up,
+            Direction {
+                vec: Vec3(1f32, 0f32, 0f32),
+            },
+        );
+        let v = Direction {
+            vec: Vec3(0.6f32, 0f32, 0.8f32),
+        };
+        for &l in &[Vec3(-0.6f32, 0f32, 0.8f32), Vec3(0f32, 0.28f32, 0.96f32)] {
+            let l = Direction { vec: l };
+            let expected = metal.evaluate(v, l, frame);
+            let actual = principled.evaluate(v, l, frame);
+            assert!((expected.g - actual.g).abs() < 0.01f32 * expected.g.max(1f32));
+            assert!((metal.pdf(v, l, frame) - principled.pdf(v, l, frame)).abs() < 0.01f32);
+        }
+
+        let polished = Principled::new(PrincipledParameters {
+            roughness: 0f32,
+            clearcoat: 1f32,
+            clearcoat_roughness: 0f32,
+            ..Default::default()
+        });
+        let l = Direction {
+            vec: Vec3(0f32, 0.28f32, 0.96f32),
+        };
+        assert!(polished.evaluate(v, l, frame).g.is_finite());
+        assert!(polished.pdf(v, l, frame).is_finite());
+        assert!(!polished.specular_lobes(v, frame).is_empty());
+    }
+}
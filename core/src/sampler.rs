@@ -0,0 +1,23 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+pub struct Sampler {
+    rng: StdRng,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Sampler {
+        Sampler {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn next_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
@@ -1,23 +1,73 @@
+use std::{collections::HashSet, rc::Rc};
+
 use crate::{
-    base_types::{Direction, HdrColor, LdrColor, Movement, Position, Transform},
+    base_types::{BoundingBox, Direction, HdrColor, Movement, Position, Transform},
+    bsdf::local_to_world,
+    light_sampling::{LightBounds, LightSampler},
     math::{Mat4, Vec3, Vec4},
+    texture::{Material, TextureContext},
+    DEFAULT_MAX_DEPTH, EPSILON,
 };
 
 pub struct Scene {
     pub camera: Box<dyn Camera>,
     pub world: Box<dyn Object>,
-    pub ambient_light: HdrColor,
+    pub environment: Box<dyn Environment>,
     pub lights: Vec<Box<dyn Light>>,
+    /// When set, each shading point samples a few lights instead of
+    /// iterating over all of them.
+    pub light_selection: Option<LightSelection>,
+    /// How many specular bounces a camera ray may take, where zero only
+    /// shades what the camera sees directly.
+    pub max_depth: usize,
+}
+
+pub struct LightSelection {
+    pub sampler: Box<dyn LightSampler>,
+    pub samples: usize,
 }
 
 pub trait Camera {
     fn get_ray(&self, position_in_image: (f32, f32)) -> Ray;
     fn position(&self) -> Position;
+
+    /// `get_ray` along with the rays through the next pixel over along
+    /// each axis of the image, `pixel_size` apart.
+    fn get_ray_with_differentials(
+        &self,
+        position_in_image: (f32, f32),
+        pixel_size: (f32, f32),
+    ) -> Ray {
+        let (x, y) = position_in_image;
+        let next_x = self.get_ray((x + pixel_size.0, y));
+        let next_y = self.get_ray((x, y + pixel_size.1));
+        Ray {
+            differentials: Some(RayDifferentials {
+                x_origin: next_x.origin,
+                x_direction: next_x.direction,
+                y_origin: next_y.origin,
+                y_direction: next_y.direction,
+            }),
+            ..self.get_ray(position_in_image)
+        }
+    }
 }
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: Position,
     pub direction: Direction,
+    /// Rays through the neighbouring pixels, telling how large an area of
+    /// a surface the ray stands for.
+    pub differentials: Option<RayDifferentials>,
+}
+
+#[derive(Clone, Copy)]
+pub struct RayDifferentials {
+    pub x_origin: Position,
+    pub x_direction: Direction,
+    pub y_origin: Position,
+    pub y_direction: Direction,
 }
 
 pub struct OrthogonalCamera {
@@ -93,6 +143,7 @@ impl Camera for OrthogonalCamera {
         Ray {
             origin,
             direction: self.direction,
+            differentials: None,
         }
     }
 
@@ -150,10 +201,11 @@ impl Camera for PerspectiveCamera {
                 self.tan_fov.1 * (position_in_image.1 * 2f32 - 1f32),
             ) * self.transform,
         );
-        return Ray {
+        Ray {
             origin: self.position,
             direction,
-        };
+            differentials: None,
+        }
     }
 
     fn position(&self) -> Position {
@@ -165,68 +217,590 @@ pub trait Object {
     fn intersect(&self, ray: &Ray) -> Option<Intersection>;
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ObjectId(pub u32);
+
+/// Where a ray meets a surface. The material is only asked for a BSDF
+/// once the hit is known to be shaded, as most hits, such as those of
+/// shadow rays, never are.
 pub struct Intersection {
+    pub object_id: ObjectId,
     pub position: Position,
     pub real_normal: Direction,
-    pub adjusted_normal: Direction,
-    pub material: Material,
+    /// Direction of increasing u along the surface, used to orient
+    /// anisotropic materials.
+    pub tangent: Direction,
+    pub context: TextureContext,
+    pub material: Rc<dyn Material>,
 }
 
-#[derive(Copy, Clone)]
-pub struct Material {
-    pub albedo: LdrColor,
-    pub roughness: f32,
-    pub f0: f32,
+impl Intersection {
+    /// The normal to shade with, perturbed by the material.
+    pub fn shading_normal(&self) -> Direction {
+        self.material.shading_normal(&self.context, self.tangent)
+    }
 }
 
-pub const F0_NORMAL: f32 = 0.04f32;
-pub const F0_GOLD: f32 = 0.75f32;
-pub const F0_SILVER: f32 = 0.97f32;
-pub const F0_COPPER: f32 = 0.83f32;
-
 pub trait Light {
     fn illuminate(
         &self,
         adjusted_position: Position,
+        receiver: ObjectId,
         world: &dyn Object,
     ) -> Option<(HdrColor, Direction)>;
+
+    fn power(&self) -> f32;
+
+    /// `None` for lights at infinity.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    /// Like `illuminate`, but picking a point on lights with extent from
+    /// `u` and reporting the density of the chosen direction. Lights
+    /// without extent return their one direction as a delta sample.
+    fn sample(
+        &self,
+        adjusted_position: Position,
+        receiver: ObjectId,
+        world: &dyn Object,
+        _u: (f32, f32),
+    ) -> Option<LightSample> {
+        let (color, direction) = self.illuminate(adjusted_position, receiver, world)?;
+        Some(LightSample {
+            color,
+            direction,
+            pdf: 1f32,
+            delta: true,
+        })
+    }
+
+    /// Radiance reaching `origin` from the light along `surface_to_light`,
+    /// if the ray hits the light before anything in `world`.
+    fn emitted(
+        &self,
+        _origin: Position,
+        _surface_to_light: Direction,
+        _receiver: ObjectId,
+        _world: &dyn Object,
+    ) -> Option<HdrColor> {
+        None
+    }
+
+    /// Solid-angle density with which `sample` picks `surface_to_light`
+    /// from `position`, zero for lights without extent.
+    fn pdf(&self, _position: Position, _surface_to_light: Direction) -> f32 {
+        0f32
+    }
+
+    /// Whether the light has no extent, so that only `sample` finds it.
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+pub struct LightSample {
+    pub color: HdrColor,
+    /// The direction the light travels in, towards the surface.
+    pub direction: Direction,
+    pub pdf: f32,
+    /// Whether the light has no extent, so that no other strategy can find
+    /// it.
+    pub delta: bool,
+}
+
+pub trait Environment {
+    fn radiance(&self, direction: Direction) -> HdrColor;
+    fn ambient(&self, normal: Direction) -> HdrColor;
+
+    fn background(&self, direction: Direction) -> Option<HdrColor> {
+        Some(self.radiance(direction))
+    }
+}
+
+/// A constant ambient term that lights surfaces but is not drawn behind them.
+impl Environment for HdrColor {
+    fn radiance(&self, _direction: Direction) -> HdrColor {
+        *self
+    }
+
+    fn ambient(&self, _normal: Direction) -> HdrColor {
+        *self
+    }
+
+    fn background(&self, _direction: Direction) -> Option<HdrColor> {
+        None
+    }
+}
+
+pub struct DirectionalLight {
+    pub direction: Direction,
+    pub color: HdrColor,
+    /// Radius of a sphere around everything the light shines on, whose
+    /// cross-section sets how much power the light delivers to the scene.
+    pub scene_radius: f32,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Direction, color: HdrColor, scene_radius: f32) -> DirectionalLight {
+        DirectionalLight {
+            direction,
+            color,
+            scene_radius,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn illuminate(
+        &self,
+        adjusted_position: Position,
+        _receiver: ObjectId,
+        world: &dyn Object,
+    ) -> Option<(HdrColor, Direction)> {
+        let shadow_ray = Ray {
+            origin: adjusted_position,
+            direction: -self.direction,
+            differentials: None,
+        };
+        if world.intersect(&shadow_ray).is_some() {
+            None
+        } else {
+            Some((self.color, self.direction))
+        }
+    }
+
+    /// The irradiance over the scene's cross-section, as in PBRT, so that it
+    /// compares with the power of lights at a position.
+    fn power(&self) -> f32 {
+        std::f32::consts::PI * self.scene_radius * self.scene_radius * self.color.luminance()
+    }
+}
+
+pub struct PointLight {
+    pub position: Position,
+    pub intensity: HdrColor,
+}
+
+impl PointLight {
+    pub fn new(position: Position, intensity: HdrColor) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn illuminate(
+        &self,
+        adjusted_position: Position,
+        _receiver: ObjectId,
+        world: &dyn Object,
+    ) -> Option<(HdrColor, Direction)> {
+        let to_light = self.position - adjusted_position;
+        let distance_squared = to_light.distance_squared();
+        let shadow_ray = Ray {
+            origin: adjusted_position,
+            direction: Direction::from_movement(to_light),
+            differentials: None,
+        };
+        if let Some(occluder) = world.intersect(&shadow_ray) {
+            if (occluder.position - adjusted_position).distance_squared() < distance_squared {
+                return None;
+            }
+        }
+        Some((
+            self.intensity * (1f32 / distance_squared),
+            -shadow_ray.direction,
+        ))
+    }
+
+    fn power(&self) -> f32 {
+        4f32 * std::f32::consts::PI * self.intensity.luminance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: BoundingBox::from_position(self.position),
+            power: self.power(),
+            axis: Direction {
+                vec: Vec3(0f32, 0f32, 1f32),
+            },
+            cos_theta_o: -1f32,
+            cos_theta_e: 0f32,
+            two_sided: false,
+        })
+    }
+}
+
+/// A glowing sphere, which lights the scene but is not drawn.
+pub struct SphereLight {
+    pub position: Position,
+    pub radius: f32,
+    pub radiance: HdrColor,
+}
+
+impl SphereLight {
+    pub fn new(position: Position, radius: f32, radiance: HdrColor) -> SphereLight {
+        SphereLight {
+            position,
+            radius,
+            radiance,
+        }
+    }
+
+    /// Cosine of the half-angle the sphere subtends and the direction to
+    /// its center, or `None` from inside.
+    fn cone(&self, position: Position) -> Option<(f32, Direction)> {
+        let to_center = self.position - position;
+        let distance_squared = to_center.distance_squared();
+        let sin_squared = self.radius * self.radius / distance_squared;
+        if sin_squared >= 1f32 {
+            return None;
+        }
+        Some((
+            (1f32 - sin_squared).sqrt(),
+            Direction::from_movement(to_center),
+        ))
+    }
+
+    /// Uniform over the cone, written so that small cones stay accurate.
+    fn cone_pdf(cos_theta_max: f32) -> f32 {
+        let sin_squared = 1f32 - cos_theta_max * cos_theta_max;
+        (1f32 + cos_theta_max) / (2f32 * std::f32::consts::PI * sin_squared)
+    }
+
+    /// Distance along the ray to the near side of the sphere.
+    fn hit_distance(&self, origin: Position, direction: Direction) -> Option<f32> {
+        let to_center = (self.position - origin).vec;
+        let along = to_center.dot(direction.vec);
+        let discriminant = along * along - to_center.dot(to_center) + self.radius * self.radius;
+        if discriminant < 0f32 {
+            return None;
+        }
+        let distance = along - discriminant.sqrt();
+        (distance >= 0f32).then_some(distance)
+    }
+
+    fn unoccluded(
+        &self,
+        origin: Position,
+        surface_to_light: Direction,
+        world: &dyn Object,
+    ) -> Option<HdrColor> {
+        let distance = self.hit_distance(origin, surface_to_light)?;
+        let shadow_ray = Ray {
+            origin,
+            direction: surface_to_light,
+            differentials: None,
+        };
+        if let Some(occluder) = world.intersect(&shadow_ray) {
+            if (occluder.position - origin).distance() < distance {
+                return None;
+            }
+        }
+        Some(self.radiance)
+    }
+}
+
+impl Light for SphereLight {
+    /// As a point light of the same power, for shading that cannot sample
+    /// the sphere.
+    fn illuminate(
+        &self,
+        adjusted_position: Position,
+        receiver: ObjectId,
+        world: &dyn Object,
+    ) -> Option<(HdrColor, Direction)> {
+        let area = std::f32::consts::PI * self.radius * self.radius;
+        PointLight::new(self.position, self.radiance * area).illuminate(
+            adjusted_position,
+            receiver,
+            world,
+        )
+    }
+
+    fn power(&self) -> f32 {
+        4f32 * std::f32::consts::PI
+            * std::f32::consts::PI
+            * self.radius
+            * self.radius
+            * self.radiance.luminance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let extent = Movement::new(self.radius, self.radius, self.radius);
+        Some(LightBounds {
+            bounds: BoundingBox::from_position(self.position + -extent)
+                .union(BoundingBox::from_position(self.position + extent)),
+            power: self.power(),
+            axis: Direction {
+                vec: Vec3(0f32, 0f32, 1f32),
+            },
+            cos_theta_o: -1f32,
+            cos_theta_e: 0f32,
+            two_sided: false,
+        })
+    }
+
+    fn sample(
+        &self,
+        adjusted_position: Position,
+        _receiver: ObjectId,
+        world: &dyn Object,
+        u: (f32, f32),
+    ) -> Option<LightSample> {
+        let (cos_theta_max, axis) = self.cone(adjusted_position)?;
+        let cos_theta = 1f32 - u.0 * (1f32 - cos_theta_max);
+        let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+        let phi = 2f32 * std::f32::consts::PI * u.1;
+        let surface_to_light = local_to_world(
+            axis,
+            Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+        );
+        Some(LightSample {
+            color: self.unoccluded(adjusted_position, surface_to_light, world)?,
+            direction: -surface_to_light,
+            pdf: SphereLight::cone_pdf(cos_theta_max),
+            delta: false,
+        })
+    }
+
+    fn emitted(
+        &self,
+        origin: Position,
+        surface_to_light: Direction,
+        _receiver: ObjectId,
+        world: &dyn Object,
+    ) -> Option<HdrColor> {
+        self.unoccluded(origin, surface_to_light, world)
+    }
+
+    fn pdf(&self, position: Position, surface_to_light: Direction) -> f32 {
+        match self.cone(position) {
+            Some((cos_theta_max, axis))
+                if axis.cos_angle_between(surface_to_light) >= cos_theta_max =>
+            {
+                SphereLight::cone_pdf(cos_theta_max)
+            }
+            _ => 0f32,
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+pub enum ObjectSet {
+    All,
+    Include(HashSet<ObjectId>),
+    Exclude(HashSet<ObjectId>),
+}
+
+impl ObjectSet {
+    pub fn contains(&self, id: ObjectId) -> bool {
+        match self {
+            ObjectSet::All => true,
+            ObjectSet::Include(ids) => ids.contains(&id),
+            ObjectSet::Exclude(ids) => !ids.contains(&id),
+        }
+    }
+}
+
+/// Restricts which objects `light` illuminates and which objects block it.
+pub struct LinkedLight {
+    pub light: Box<dyn Light>,
+    pub illuminates: ObjectSet,
+    pub shadowed_by: ObjectSet,
+    pub casts_shadows: bool,
+}
+
+impl LinkedLight {
+    pub fn new(light: Box<dyn Light>) -> LinkedLight {
+        LinkedLight {
+            light,
+            illuminates: ObjectSet::All,
+            shadowed_by: ObjectSet::All,
+            casts_shadows: true,
+        }
+    }
+}
+
+impl Light for LinkedLight {
+    fn illuminate(
+        &self,
+        adjusted_position: Position,
+        receiver: ObjectId,
+        world: &dyn Object,
+    ) -> Option<(HdrColor, Direction)> {
+        if !self.illuminates.contains(receiver) {
+            return None;
+        }
+        if self.casts_shadows {
+            self.light.illuminate(
+                adjusted_position,
+                receiver,
+                &ShadowCasters {
+                    world,
+                    casters: &self.shadowed_by,
+                },
+            )
+        } else {
+            self.light
+                .illuminate(adjusted_position, receiver, &Vec::new())
+        }
+    }
+
+    fn power(&self) -> f32 {
+        self.light.power()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        self.light.bounds()
+    }
+
+    fn sample(
+        &self,
+        adjusted_position: Position,
+        receiver: ObjectId,
+        world: &dyn Object,
+        u: (f32, f32),
+    ) -> Option<LightSample> {
+        if !self.illuminates.contains(receiver) {
+            return None;
+        }
+        if self.casts_shadows {
+            self.light.sample(
+                adjusted_position,
+                receiver,
+                &ShadowCasters {
+                    world,
+                    casters: &self.shadowed_by,
+                },
+                u,
+            )
+        } else {
+            self.light
+                .sample(adjusted_position, receiver, &Vec::new(), u)
+        }
+    }
+
+    fn emitted(
+        &self,
+        origin: Position,
+        surface_to_light: Direction,
+        receiver: ObjectId,
+        world: &dyn Object,
+    ) -> Option<HdrColor> {
+        if !self.illuminates.contains(receiver) {
+            return None;
+        }
+        if self.casts_shadows {
+            self.light.emitted(
+                origin,
+                surface_to_light,
+                receiver,
+                &ShadowCasters {
+                    world,
+                    casters: &self.shadowed_by,
+                },
+            )
+        } else {
+            self.light
+                .emitted(origin, surface_to_light, receiver, &Vec::new())
+        }
+    }
+
+    fn pdf(&self, position: Position, surface_to_light: Direction) -> f32 {
+        self.light.pdf(position, surface_to_light)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.light.is_delta()
+    }
+}
+
+/// The part of `world` made of `casters`; rays pass through everything else.
+struct ShadowCasters<'a> {
+    world: &'a dyn Object,
+    casters: &'a ObjectSet,
+}
+
+/// Surfaces a shadow ray passes through before it counts as unblocked.
+const MAX_SKIPPED_SURFACES: usize = 64;
+
+impl Object for ShadowCasters<'_> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let mut origin = ray.origin;
+        for _ in 0..MAX_SKIPPED_SURFACES {
+            let intersection = self.world.intersect(&Ray {
+                origin,
+                direction: ray.direction,
+                differentials: None,
+            })?;
+            if self.casters.contains(intersection.object_id) {
+                return Some(intersection);
+            }
+            origin = intersection.position + ray.direction * EPSILON;
+        }
+        None
+    }
+}
+
+impl Object for Vec<Box<dyn Object>> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        self.iter()
+            .filter_map(|object| object.intersect(ray))
+            .min_by(|a, b| {
+                (a.position - ray.origin)
+                    .distance_squared()
+                    .total_cmp(&(b.position - ray.origin).distance_squared())
+            })
+    }
 }
 
 impl Scene {
     pub fn new(
         camera: Box<dyn Camera>,
         world: Box<dyn Object>,
-        ambient_light: HdrColor,
+        environment: Box<dyn Environment>,
         lights: Vec<Box<dyn Light>>,
     ) -> Scene {
         Scene {
             camera,
             world,
-            ambient_light,
+            environment,
             lights,
+            light_selection: None,
+            max_depth: DEFAULT_MAX_DEPTH,
         }
     }
 }
 
 pub struct Plane {
+    pub id: ObjectId,
     pub transform: Transform,
     pub coefficient_x0y0z0: f32,
     pub coefficient_x1y0z0: f32,
     pub coefficient_x0y1z0: f32,
     pub coefficient_x0y0z1: f32,
-    pub material: Material,
+    pub material: Rc<dyn Material>,
 }
 
 impl Plane {
     pub fn new(
+        id: ObjectId,
         transform: Transform,
         coefficient_x0y0z0: f32,
         coefficient_x1y0z0: f32,
         coefficient_x0y1z0: f32,
         coefficient_x0y0z1: f32,
-        material: Material,
+        material: Rc<dyn Material>,
     ) -> Plane {
         Plane {
+            id,
             transform,
             coefficient_x0y0z0,
             coefficient_x1y0z0,
@@ -239,50 +813,202 @@ impl Plane {
 
 impl Object for Plane {
     fn intersect(&self, ray: &Ray) -> Option<Intersection> {
-        let Ray { origin, direction } = *ray;
-        let Position { vec: Vec3(p, q, r) } = origin;
-        let Direction { vec: Vec3(u, v, w) } = direction;
+        let Ray {
+            origin,
+            direction,
+            differentials,
+        } = *ray;
 
-        let mut coefficient_t1 = 0f32;
-        let mut coefficient_t0 = 0f32;
+        let distance = |origin: Position, direction: Direction| {
+            let Position { vec: Vec3(p, q, r) } = origin;
+            let Direction { vec: Vec3(u, v, w) } = direction;
 
-        {
-            coefficient_t0 += self.coefficient_x0y0z0;
-        }
-        {
-            coefficient_t1 += self.coefficient_x1y0z0 * u;
-            coefficient_t0 += self.coefficient_x1y0z0 * p;
-        }
-        {
-            coefficient_t1 += self.coefficient_x0y1z0 * v;
-            coefficient_t0 += self.coefficient_x0y1z0 * q;
-        }
-        {
-            coefficient_t1 += self.coefficient_x0y0z1 * w;
-            coefficient_t0 += self.coefficient_x0y0z1 * r;
-        }
+            let mut coefficient_t1 = 0f32;
+            let mut coefficient_t0 = 0f32;
 
-        let t = -coefficient_t0 / coefficient_t1;
+            {
+                coefficient_t0 += self.coefficient_x0y0z0;
+            }
+            {
+                coefficient_t1 += self.coefficient_x1y0z0 * u;
+                coefficient_t0 += self.coefficient_x1y0z0 * p;
+            }
+            {
+                coefficient_t1 += self.coefficient_x0y1z0 * v;
+                coefficient_t0 += self.coefficient_x0y1z0 * q;
+            }
+            {
+                coefficient_t1 += self.coefficient_x0y0z1 * w;
+                coefficient_t0 += self.coefficient_x0y0z1 * r;
+            }
 
-        if t < 0f32 {
+            -coefficient_t0 / coefficient_t1
+        };
+        let t = distance(origin, direction);
+
+        // Rays parallel to the plane, or lying in it, never meet it.
+        if !t.is_finite() || t <= 0f32 {
             None
         } else {
             let position = origin + direction * t;
+            let normal = Direction::from_movement(Movement::new(
+                self.coefficient_x1y0z0,
+                self.coefficient_x0y1z0,
+                self.coefficient_x0y0z1,
+            ));
+            // The plane is parametrised by distances along the x axis, or the
+            // y axis if it is perpendicular to x, projected onto the plane.
+            let axis = if normal.vec.0.abs() < 0.999f32 {
+                Vec3(1f32, 0f32, 0f32)
+            } else {
+                Vec3(0f32, 1f32, 0f32)
+            };
+            let tangent = Direction {
+                vec: (axis - normal.vec * normal.vec.dot(axis)).normalize(),
+            };
+            let bitangent = normal.vec.cross(tangent.vec);
+            let uv_at =
+                |position: Position| (position.vec.dot(tangent.vec), position.vec.dot(bitangent));
+            let uv = uv_at(position);
+            // Where the neighbouring rays meet the plane.
+            let (duv_dx, duv_dy) = match differentials {
+                Some(differentials) => {
+                    let offset = |origin: Position, direction: Direction| {
+                        let (u, v) = uv_at(origin + direction * distance(origin, direction));
+                        (u - uv.0, v - uv.1)
+                    };
+                    (
+                        offset(differentials.x_origin, differentials.x_direction),
+                        offset(differentials.y_origin, differentials.y_direction),
+                    )
+                }
+                None => ((0f32, 0f32), (0f32, 0f32)),
+            };
 
+            let context = TextureContext {
+                position,
+                uv,
+                duv_dx,
+                duv_dy,
+                normal,
+            };
             Some(Intersection {
+                object_id: self.id,
                 position,
-                real_normal: Direction::from_movement(Movement::new(0f32, 0f32, 1f32)),
-                adjusted_normal: Direction::from_movement(Movement::new(0f32, 0f32, 1f32)),
-                // material: Material {
-                //     albedo: LdrColor::new(
-                //         position.vec.0 - position.vec.0.floor(),
-                //         position.vec.1 - position.vec.1.floor(),
-                //         position.vec.2 - position.vec.2.floor(),
-                //     ),
-                //     ..self.material.clone()
-                // },
+                real_normal: normal,
+                tangent,
+                context,
                 material: self.material.clone(),
             })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::base_types::LdrColor;
+    use crate::bsdf::{CookTorrance, F0_NORMAL};
+
+    const MATERIAL: CookTorrance = CookTorrance {
+        albedo: LdrColor {
+            r: 1f32,
+            g: 1f32,
+            b: 1f32,
+        },
+        roughness_u: 0.5f32,
+        roughness_v: 0.5f32,
+        f0: F0_NORMAL,
+        thin_film: None,
+    };
+
+    fn floor_and_ceiling() -> Vec<Box<dyn Object>> {
+        vec![
+            Box::new(Plane::new(
+                ObjectId(0),
+                Transform::I,
+                1f32,
+                0f32,
+                0f32,
+                1f32,
+                Rc::new(MATERIAL),
+            )),
+            Box::new(Plane::new(
+                ObjectId(1),
+                Transform::I,
+                -1f32,
+                0f32,
+                0f32,
+                1f32,
+                Rc::new(MATERIAL),
+            )),
+        ]
+    }
+
+    fn sunlight() -> Box<dyn Light> {
+        Box::new(DirectionalLight::new(
+            Direction::from_movement(Movement::new(0f32, 0f32, -1f32)),
+            HdrColor::new(1f32, 1f32, 1f32),
+            2f32,
+        ))
+    }
+
+    #[test]
+    fn test_light_linking() {
+        let world = floor_and_ceiling();
+        let on_floor = Position::new(0f32, 0f32, -1f32 + EPSILON);
+
+        let unlinked = LinkedLight::new(sunlight());
+        assert!(unlinked.illuminate(on_floor, ObjectId(0), &world).is_none());
+
+        let ceiling_ignored = LinkedLight {
+            shadowed_by: ObjectSet::Exclude(HashSet::from([ObjectId(1)])),
+            ..LinkedLight::new(sunlight())
+        };
+        assert!(ceiling_ignored
+            .illuminate(on_floor, ObjectId(0), &world)
+            .is_some());
+
+        let shadowless = LinkedLight {
+            casts_shadows: false,
+            ..LinkedLight::new(sunlight())
+        };
+        assert!(shadowless
+            .illuminate(on_floor, ObjectId(0), &world)
+            .is_some());
+
+        let ceiling_only = LinkedLight {
+            illuminates: ObjectSet::Include(HashSet::from([ObjectId(1)])),
+            casts_shadows: false,
+            ..LinkedLight::new(sunlight())
+        };
+        assert!(ceiling_only
+            .illuminate(on_floor, ObjectId(0), &world)
+            .is_none());
+
+        // A wall the shadow ray runs along is not hit at all.
+        let mut world = floor_and_ceiling();
+        world.push(Box::new(Plane::new(
+            ObjectId(2),
+            Transform::I,
+            0f32,
+            1f32,
+            0f32,
+            0f32,
+            Rc::new(MATERIAL),
+        )));
+        assert!(ceiling_ignored
+            .illuminate(on_floor, ObjectId(0), &world)
+            .is_some());
+        let hit = world
+            .intersect(&Ray {
+                origin: on_floor,
+                direction: Direction::from_movement(Movement::new(0f32, 0f32, 1f32)),
+                differentials: None,
+            })
+            .unwrap();
+        assert_eq!(hit.object_id, ObjectId(1));
+        assert!((hit.position - Position::new(0f32, 0f32, 1f32)).distance() < EPSILON);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    base_types::{Direction, HdrColor},
+    math::Vec3,
+    scene::{DirectionalLight, Environment},
+};
+
+const SH_THETA_STEPS: usize = 32;
+const SH_PHI_STEPS: usize = 64;
+
+pub struct PreethamSky {
+    sun_direction: Direction,
+    turbidity: f32,
+    zenith: Vec3,
+    perez: [[f32; 5]; 3],
+    perez_at_zenith: Vec3,
+    scale: f32,
+    ambient_sh: [Vec3; 9],
+}
+
+impl PreethamSky {
+    /// `elevation` is the angle of the sun above the horizon (the xy plane),
+    /// `azimuth` is measured from the +y axis towards the +x axis, both in
+    /// radians. `turbidity` ranges from 2 (clear) to about 10 (hazy) and
+    /// `scale` converts the model's kcd/m² into scene radiance units.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, scale: f32) -> PreethamSky {
+        let elevation = elevation.clamp(0f32, std::f32::consts::FRAC_PI_2);
+        let sun_direction = Direction {
+            vec: Vec3(
+                azimuth.sin() * elevation.cos(),
+                azimuth.cos() * elevation.cos(),
+                elevation.sin(),
+            ),
+        };
+        let theta_sun = std::f32::consts::FRAC_PI_2 - elevation;
+        let t = turbidity;
+
+        let chi = (4f32 / 9f32 - t / 120f32) * (std::f32::consts::PI - 2f32 * theta_sun);
+        let zenith_luminance = (4.0453f32 * t - 4.971f32) * chi.tan() - 0.2155f32 * t + 2.4192f32;
+        let theta = [
+            theta_sun * theta_sun * theta_sun,
+            theta_sun * theta_sun,
+            theta_sun,
+            1f32,
+        ];
+        let chromaticity = |coefficients: [[f32; 4]; 3]| {
+            let row = |c: [f32; 4]| c.iter().zip(theta.iter()).map(|(c, t)| c * t).sum::<f32>();
+            t * t * row(coefficients[0]) + t * row(coefficients[1]) + row(coefficients[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166f32, -0.00375f32, 0.00209f32, 0f32],
+            [-0.02903f32, 0.06377f32, -0.03202f32, 0.00394f32],
+            [0.11693f32, -0.21196f32, 0.06052f32, 0.25886f32],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275f32, -0.0061f32, 0.00317f32, 0f32],
+            [-0.04214f32, 0.0897f32, -0.04153f32, 0.00516f32],
+            [0.15346f32, -0.26756f32, 0.0667f32, 0.26688f32],
+        ]);
+
+        let perez = [
+            [
+                0.1787f32 * t - 1.463f32,
+                -0.3554f32 * t + 0.4275f32,
+                -0.0227f32 * t + 5.3251f32,
+                0.1206f32 * t - 2.5771f32,
+                -0.067f32 * t + 0.3703f32,
+            ],
+            [
+                -0.0193f32 * t - 0.2592f32,
+                -0.0665f32 * t + 0.0008f32,
+                -0.0004f32 * t + 0.2125f32,
+                -0.0641f32 * t - 0.8989f32,
+                -0.0033f32 * t + 0.0452f32,
+            ],
+            [
+                -0.0167f32 * t - 0.2608f32,
+                -0.095f32 * t + 0.0092f32,
+                -0.0079f32 * t + 0.2102f32,
+                -0.0441f32 * t - 1.6537f32,
+                -0.0109f32 * t + 0.0529f32,
+            ],
+        ];
+        let perez_at_zenith = Vec3(
+            perez_function(perez[0], 0f32, theta_sun),
+            perez_function(perez[1], 0f32, theta_sun),
+            perez_function(perez[2], 0f32, theta_sun),
+        );
+
+        let mut sky = PreethamSky {
+            sun_direction,
+            turbidity,
+            zenith: Vec3(zenith_luminance, zenith_x, zenith_y),
+            perez,
+            perez_at_zenith,
+            scale,
+            ambient_sh: [Vec3(0f32, 0f32, 0f32); 9],
+        };
+        sky.ambient_sh = sky.project_ambient();
+        sky
+    }
+
+    /// Direction from the ground towards the sun.
+    pub fn sun_direction(&self) -> Direction {
+        self.sun_direction
+    }
+
+    /// A light standing in for the solar disk, whose colour is `irradiance`
+    /// attenuated by Rayleigh and aerosol extinction along the sun's path
+    /// through the atmosphere. `scene_radius` bounds what it shines on.
+    pub fn sun(&self, irradiance: f32, scene_radius: f32) -> DirectionalLight {
+        let cos_theta_sun = self.sun_direction.vec.2;
+        let theta_sun_degrees = cos_theta_sun.acos().to_degrees();
+        let relative_air_mass =
+            1f32 / (cos_theta_sun + 0.15f32 * (93.885f32 - theta_sun_degrees).powf(-1.253f32));
+        let beta = 0.04608f32 * self.turbidity - 0.04586f32;
+        let transmittance = |wavelength_um: f32| {
+            let rayleigh = 0.008735f32 * wavelength_um.powf(-4.08f32);
+            let aerosol = beta * wavelength_um.powf(-1.3f32);
+            (-(rayleigh + aerosol) * relative_air_mass).exp()
+        };
+        DirectionalLight::new(
+            -self.sun_direction,
+            HdrColor::new(
+                irradiance * transmittance(0.68f32),
+                irradiance * transmittance(0.55f32),
+                irradiance * transmittance(0.44f32),
+            ),
+            scene_radius,
+        )
+    }
+
+    /// Black below the horizon, where the ground hides the sky.
+    fn sky_rgb(&self, direction: Direction) -> Vec3 {
+        let Vec3(x, y, z) = direction.vec;
+        if z < 0f32 {
+            return Vec3(0f32, 0f32, 0f32);
+        }
+        let direction = Vec3(x, y, z.max(0.001f32)).normalize();
+        let theta = direction.2.acos();
+        let gamma = direction
+            .dot(self.sun_direction.vec)
+            .clamp(-1f32, 1f32)
+            .acos();
+
+        let luminance =
+            self.zenith.0 * perez_function(self.perez[0], theta, gamma) / self.perez_at_zenith.0;
+        let x =
+            self.zenith.1 * perez_function(self.perez[1], theta, gamma) / self.perez_at_zenith.1;
+        let y =
+            self.zenith.2 * perez_function(self.perez[2], theta, gamma) / self.perez_at_zenith.2;
+
+        let luminance = luminance * self.scale;
+        let cie_x = x / y * luminance;
+        let cie_z = (1f32 - x - y) / y * luminance;
+        Vec3(
+            3.2406f32 * cie_x - 1.5372f32 * luminance - 0.4986f32 * cie_z,
+            -0.9689f32 * cie_x + 1.8758f32 * luminance + 0.0415f32 * cie_z,
+            0.0557f32 * cie_x - 0.204f32 * luminance + 1.057f32 * cie_z,
+        )
+    }
+
+    /// Projects the sky onto spherical harmonics, over the upper hemisphere
+    /// only as there is none below.
+    fn project_ambient(&self) -> [Vec3; 9] {
+        let mut coefficients = [Vec3(0f32, 0f32, 0f32); 9];
+        let d_theta = std::f32::consts::FRAC_PI_2 / SH_THETA_STEPS as f32;
+        let d_phi = 2f32 * std::f32::consts::PI / SH_PHI_STEPS as f32;
+        for i in 0..SH_THETA_STEPS {
+            let theta = (i as f32 + 0.5f32) * d_theta;
+            let solid_angle = theta.sin() * d_theta * d_phi;
+            for j in 0..SH_PHI_STEPS {
+                let phi = (j as f32 + 0.5f32) * d_phi;
+                let direction = Direction {
+                    vec: Vec3(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    ),
+                };
+                let radiance = self.sky_rgb(direction);
+                for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                    *coefficient = *coefficient + radiance * (basis * solid_angle);
+                }
+            }
+        }
+        coefficients
+    }
+}
+
+fn perez_function(coefficients: [f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = coefficients;
+    let cos_gamma = gamma.cos();
+    (1f32 + a * (b / theta.cos().max(0.001f32)).exp())
+        * (1f32 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+fn sh_basis(direction: Direction) -> [f32; 9] {
+    let Vec3(x, y, z) = direction.vec;
+    [
+        0.282095f32,
+        0.488603f32 * y,
+        0.488603f32 * z,
+        0.488603f32 * x,
+        1.092548f32 * x * y,
+        1.092548f32 * y * z,
+        0.315392f32 * (3f32 * z * z - 1f32),
+        1.092548f32 * x * z,
+        0.546274f32 * (x * x - y * y),
+    ]
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, direction: Direction) -> HdrColor {
+        let Vec3(r, g, b) = self.sky_rgb(direction);
+        HdrColor::new(r, g, b)
+    }
+
+    fn ambient(&self, normal: Direction) -> HdrColor {
+        // Ramamoorthi and Hanrahan's convolution of the radiance with the
+        // clamped cosine, divided by pi to get the outgoing radiance of a
+        // white Lambertian surface.
+        const BAND_WEIGHTS: [f32; 9] = [
+            1f32,
+            2f32 / 3f32,
+            2f32 / 3f32,
+            2f32 / 3f32,
+            1f32 / 4f32,
+            1f32 / 4f32,
+            1f32 / 4f32,
+            1f32 / 4f32,
+            1f32 / 4f32,
+        ];
+        let Vec3(r, g, b) = self
+            .ambient_sh
+            .iter()
+            .zip(sh_basis(normal))
+            .zip(BAND_WEIGHTS)
+            .fold(
+                Vec3(0f32, 0f32, 0f32),
+                |acc, ((coefficient, basis), weight)| acc + *coefficient * (basis * weight),
+            );
+        HdrColor::new(r, g, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sun_and_anti_sun_luminance_ratio() {
+        let (elevation, azimuth) = (0.5f32, 0.7f32);
+        let sky = PreethamSky::new(elevation, azimuth, 3f32, 1f32);
+        let luminance = |azimuth: f32| {
+            let Vec3(r, g, b) = sky.sky_rgb(Direction {
+                vec: Vec3(
+                    azimuth.sin() * elevation.cos(),
+                    azimuth.cos() * elevation.cos(),
+                    elevation.sin(),
+                ),
+            });
+            0.2126f32 * r + 0.7152f32 * g + 0.0722f32 * b
+        };
+        // At equal elevation only the Perez terms in the angle to the sun
+        // differ. With Preetham's luminance coefficients for turbidity 3,
+        // C = 5.257, D = -2.2153 and E = 0.1693, and the anti-sun direction
+        // pi - 2 elevation away from the sun, their ratio is 5.868.
+        let ratio = luminance(azimuth) / luminance(azimuth + std::f32::consts::PI);
+        assert!((ratio - 5.868f32).abs() < 0.01f32 * 5.868f32);
+    }
+
+    #[test]
+    fn test_ground_is_not_lit_by_sky() {
+        let sky = PreethamSky::new(0.5f32, 0f32, 3f32, 1f32);
+        let down = Direction {
+            vec: Vec3(0f32, 0f32, -1f32),
+        };
+        assert_eq!(sky.radiance(down).g, 0f32);
+        let up = Direction {
+            vec: Vec3(0f32, 0f32, 1f32),
+        };
+        assert!(sky.ambient(down).g < 0.1f32 * sky.ambient(up).g);
+    }
+
+    #[test]
+    fn test_ambient_of_upward_normal_is_sky_average() {
+        let sky = PreethamSky::new(0.5f32, 0f32, 3f32, 1f32);
+        let up = Direction {
+            vec: Vec3(0f32, 0f32, 1f32),
+        };
+        let steps = 256;
+        let mut expected = 0f32;
+        for i in 0..steps {
+            let theta = (i as f32 + 0.5f32) / steps as f32 * std::f32::consts::FRAC_PI_2;
+            for j in 0..steps {
+                let phi = (j as f32 + 0.5f32) / steps as f32 * 2f32 * std::f32::consts::PI;
+                let direction = Direction {
+                    vec: Vec3(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    ),
+                };
+                expected += sky.sky_rgb(direction).1 * theta.cos() * theta.sin();
+            }
+        }
+        expected *= std::f32::consts::FRAC_PI_2 / steps as f32 * 2f32 / steps as f32;
+        let actual = sky.ambient(up).g;
+        assert!((actual - expected).abs() < 0.1f32 * expected);
+    }
+}
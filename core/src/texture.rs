@@ -0,0 +1,412 @@
+use std::rc::Rc;
+
+use crate::{
+    base_types::{Direction, HdrColor, LdrColor, Position},
+    bsdf::{Bsdf, CookTorrance, Emissive, ShadingFrame, F0_NORMAL},
+    math::Vec3,
+};
+
+/// Where on a surface a texture is looked up.
+#[derive(Clone, Copy)]
+pub struct TextureContext {
+    pub position: Position,
+    pub uv: (f32, f32),
+    /// How far the UV coordinates move from one pixel to the next along
+    /// each axis of the image, or zero when unknown.
+    pub duv_dx: (f32, f32),
+    pub duv_dy: (f32, f32),
+    pub normal: Direction,
+}
+
+pub trait Texture<T> {
+    fn evaluate(&self, context: &TextureContext) -> T;
+}
+
+/// Values textures can blend between.
+pub trait Interpolate: Copy {
+    fn lerp(self, rhs: Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn lerp(self, rhs: f32, t: f32) -> f32 {
+        self + (rhs - self) * t
+    }
+}
+
+impl Interpolate for LdrColor {
+    fn lerp(self, rhs: LdrColor, t: f32) -> LdrColor {
+        LdrColor::new(
+            self.r.lerp(rhs.r, t),
+            self.g.lerp(rhs.g, t),
+            self.b.lerp(rhs.b, t),
+        )
+    }
+}
+
+impl Interpolate for HdrColor {
+    fn lerp(self, rhs: HdrColor, t: f32) -> HdrColor {
+        HdrColor::new(
+            self.r.lerp(rhs.r, t),
+            self.g.lerp(rhs.g, t),
+            self.b.lerp(rhs.b, t),
+        )
+    }
+}
+
+pub struct Constant<T>(pub T);
+
+impl<T: Copy> Texture<T> for Constant<T> {
+    fn evaluate(&self, _context: &TextureContext) -> T {
+        self.0
+    }
+}
+
+/// Alternates between `even` and `odd` on a grid of `size` wide squares in
+/// UV space.
+pub struct Checkerboard<T> {
+    pub even: T,
+    pub odd: T,
+    pub size: f32,
+}
+
+impl<T: Copy> Texture<T> for Checkerboard<T> {
+    fn evaluate(&self, context: &TextureContext) -> T {
+        let (u, v) = context.uv;
+        let cell = (u / self.size).floor() + (v / self.size).floor();
+        if cell.rem_euclid(2f32) == 0f32 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+/// Blends linearly from `from` at `origin` to `to` one `length` further
+/// along `direction`, clamping beyond either end.
+pub struct Gradient<T> {
+    pub from: T,
+    pub to: T,
+    pub origin: Position,
+    pub direction: Direction,
+    pub length: f32,
+}
+
+impl<T: Interpolate> Texture<T> for Gradient<T> {
+    fn evaluate(&self, context: &TextureContext) -> T {
+        // A gradient without length has nowhere to blend.
+        if self.length == 0f32 {
+            return self.from;
+        }
+        let distance = (context.position - self.origin).vec.dot(self.direction.vec);
+        self.from
+            .lerp(self.to, (distance / self.length).clamp(0f32, 1f32))
+    }
+}
+
+/// Looks `texture` up in three planar projections of the position along the
+/// world axes, blended by how squarely the surface faces each axis, for
+/// objects without sensible UVs.
+pub struct Triplanar<T> {
+    pub texture: Box<dyn Texture<T>>,
+    /// UV units per world unit.
+    pub scale: f32,
+    /// How abruptly the projections hand over to each other, where 1 blends
+    /// by the normal's components and larger values narrow the seams.
+    pub sharpness: f32,
+}
+
+impl<T: Interpolate> Texture<T> for Triplanar<T> {
+    fn evaluate(&self, context: &TextureContext) -> T {
+        let Vec3(x, y, z) = context.position.vec * self.scale;
+        let Vec3(nx, ny, nz) = context.normal.vec;
+        let weights = [nx, ny, nz].map(|n| n.abs().powf(self.sharpness));
+        let total: f32 = weights.iter().sum();
+        // The footprint of each projection is unknown, so lookups are
+        // unfiltered.
+        let lookup = |uv: (f32, f32)| {
+            self.texture.evaluate(&TextureContext {
+                uv,
+                duv_dx: (0f32, 0f32),
+                duv_dy: (0f32, 0f32),
+                ..*context
+            })
+        };
+        let along_x = lookup((y, z));
+        let along_y = lookup((x, z));
+        let along_z = lookup((x, y));
+        along_x
+            .lerp(
+                along_y,
+                weights[1] / (weights[0] + weights[1]).max(f32::MIN_POSITIVE),
+            )
+            .lerp(along_z, weights[2] / total)
+    }
+}
+
+/// Chooses the BSDF of a surface at each hit, so that its parameters can
+/// vary over the surface.
+pub trait Material {
+    /// Takes the material by `Rc`, so that one without parameters to look
+    /// up can hand out itself instead of a new BSDF at every hit.
+    fn bsdf(self: Rc<Self>, context: &TextureContext) -> Rc<dyn Bsdf>;
+
+    /// The normal to shade with, given the tangent pointing along
+    /// increasing u.
+    fn shading_normal(&self, context: &TextureContext, _tangent: Direction) -> Direction {
+        context.normal
+    }
+}
+
+impl<B: Bsdf + 'static> Material for B {
+    fn bsdf(self: Rc<Self>, _context: &TextureContext) -> Rc<dyn Bsdf> {
+        self
+    }
+}
+
+/// Shares one BSDF between several objects' materials.
+impl<B: Bsdf + 'static> Material for Rc<B> {
+    fn bsdf(self: Rc<Self>, _context: &TextureContext) -> Rc<dyn Bsdf> {
+        (*self).clone()
+    }
+}
+
+/// A Cook-Torrance surface whose parameters come from textures.
+pub struct TexturedCookTorrance {
+    pub albedo: Box<dyn Texture<LdrColor>>,
+    pub roughness: Box<dyn Texture<f32>>,
+    pub f0: Box<dyn Texture<LdrColor>>,
+    pub emission: Box<dyn Texture<HdrColor>>,
+}
+
+impl Material for TexturedCookTorrance {
+    fn bsdf(self: Rc<Self>, context: &TextureContext) -> Rc<dyn Bsdf> {
+        let roughness = self.roughness.evaluate(context);
+        Rc::new(Emissive {
+            base: CookTorrance {
+                albedo: self.albedo.evaluate(context),
+                roughness_u: roughness,
+                roughness_v: roughness,
+                f0: self.f0.evaluate(context),
+                thin_film: None,
+            },
+            emission: self.emission.evaluate(context),
+        })
+    }
+}
+
+/// The metal-roughness workflow of most asset libraries: metals reflect
+/// their base colour, everything else diffuses it under a 4% specular.
+pub struct MetallicRoughness {
+    pub base_color: Box<dyn Texture<LdrColor>>,
+    pub roughness: Box<dyn Texture<f32>>,
+    pub metallic: Box<dyn Texture<f32>>,
+    pub emission: Box<dyn Texture<HdrColor>>,
+}
+
+impl Material for MetallicRoughness {
+    fn bsdf(self: Rc<Self>, context: &TextureContext) -> Rc<dyn Bsdf> {
+        let base_color = self.base_color.evaluate(context);
+        let roughness = self.roughness.evaluate(context);
+        let metallic = self.metallic.evaluate(context).clamp(0f32, 1f32);
+        Rc::new(Emissive {
+            base: CookTorrance {
+                albedo: LdrColor::new(0f32, 0f32, 0f32).lerp(base_color, 1f32 - metallic),
+                roughness_u: roughness,
+                roughness_v: roughness,
+                f0: F0_NORMAL.lerp(base_color, metallic),
+                thin_film: None,
+            },
+            emission: self.emission.evaluate(context),
+        })
+    }
+}
+
+/// Tilts the normal of `material` by a tangent-space normal map, whose red,
+/// green and blue channels hold the components along the tangent, the
+/// bitangent and the normal, encoded from [-1, 1] into [0, 1].
+pub struct NormalMap<M: Material> {
+    pub material: Rc<M>,
+    pub normals: Box<dyn Texture<HdrColor>>,
+    /// Scales the tilt, where 1 applies the map as authored.
+    pub strength: f32,
+}
+
+impl<M: Material> Material for NormalMap<M> {
+    fn bsdf(self: Rc<Self>, context: &TextureContext) -> Rc<dyn Bsdf> {
+        self.material.clone().bsdf(context)
+    }
+
+    fn shading_normal(&self, context: &TextureContext, tangent: Direction) -> Direction {
+        let normal = self.material.shading_normal(context, tangent);
+        let HdrColor { r, g, b } = self.normals.evaluate(context);
+        let (x, y, z) = (
+            (r * 2f32 - 1f32) * self.strength,
+            (g * 2f32 - 1f32) * self.strength,
+            b * 2f32 - 1f32,
+        );
+        let frame = ShadingFrame::new(normal, normal, tangent);
+        frame.to_world(Vec3(x, y, z.max(0f32)))
+    }
+}
+
+/// Tilts the normal of `material` as if its surface were raised by
+/// `scale` times `height`, measured in the same units as UV.
+pub struct BumpMap<M: Material> {
+    pub material: Rc<M>,
+    pub height: Box<dyn Texture<f32>>,
+    pub scale: f32,
+}
+
+/// Step in UV used to take differences of the height when the footprint of
+/// the lookup is unknown.
+const BUMP_DELTA: f32 = 0.0005f32;
+
+impl<M: Material> Material for BumpMap<M> {
+    fn bsdf(self: Rc<Self>, context: &TextureContext) -> Rc<dyn Bsdf> {
+        self.material.clone().bsdf(context)
+    }
+
+    fn shading_normal(&self, context: &TextureContext, tangent: Direction) -> Direction {
+        let normal = self.material.shading_normal(context, tangent);
+        // Differences over half a pixel keep the bumps from aliasing.
+        let step = |(du, dv): (f32, f32)| (du * du + dv * dv).sqrt() * 0.5f32;
+        let delta = step(context.duv_dx)
+            .max(step(context.duv_dy))
+            .max(BUMP_DELTA);
+        let frame = ShadingFrame::new(normal, normal, tangent);
+        // Solid textures see the same step taken along the surface.
+        let (u, v) = context.uv;
+        let height = |uv: (f32, f32), step: Direction| {
+            self.height.evaluate(&TextureContext {
+                position: context.position + step * delta,
+                uv,
+                ..*context
+            })
+        };
+        let center = self.height.evaluate(context);
+        let dh_du = (height((u + delta, v), tangent) - center) / delta;
+        let dh_dv = (height((u, v + delta), frame.bitangent()) - center) / delta;
+        frame.to_world(Vec3(-dh_du * self.scale, -dh_dv * self.scale, 1f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_textures() {
+        let context = |x: f32, y: f32| TextureContext {
+            position: Position::new(x, y, 0f32),
+            uv: (x, y),
+            duv_dx: (0f32, 0f32),
+            duv_dy: (0f32, 0f32),
+            normal: Direction {
+                vec: Vec3(0f32, 0f32, 1f32),
+            },
+        };
+
+        let checkerboard = Checkerboard {
+            even: 0f32,
+            odd: 1f32,
+            size: 0.5f32,
+        };
+        assert_eq!(checkerboard.evaluate(&context(0.1f32, 0.1f32)), 0f32);
+        assert_eq!(checkerboard.evaluate(&context(0.6f32, 0.1f32)), 1f32);
+        assert_eq!(checkerboard.evaluate(&context(-0.1f32, 0.1f32)), 1f32);
+        assert_eq!(checkerboard.evaluate(&context(-0.1f32, -0.1f32)), 0f32);
+
+        let gradient = Gradient {
+            from: 0f32,
+            to: 1f32,
+            origin: Position::new(1f32, 0f32, 0f32),
+            direction: Direction {
+                vec: Vec3(1f32, 0f32, 0f32),
+            },
+            length: 2f32,
+        };
+        assert_eq!(gradient.evaluate(&context(0f32, 5f32)), 0f32);
+        assert!((gradient.evaluate(&context(2f32, 5f32)) - 0.5f32).abs() < 0.00042f32);
+        assert_eq!(gradient.evaluate(&context(4f32, 5f32)), 1f32);
+        let point = Gradient {
+            length: 0f32,
+            ..gradient
+        };
+        assert_eq!(point.evaluate(&context(1f32, 5f32)), 0f32);
+
+        let triplanar = Triplanar {
+            texture: Box::new(checkerboard),
+            scale: 2f32,
+            sharpness: 4f32,
+        };
+        let facing = |normal: Vec3| TextureContext {
+            position: Position::new(0.25f32, 0.1f32, 0.75f32),
+            normal: Direction {
+                vec: normal.normalize(),
+            },
+            ..context(0f32, 0f32)
+        };
+        assert_eq!(triplanar.evaluate(&facing(Vec3(1f32, 0f32, 0f32))), 1f32);
+        assert_eq!(triplanar.evaluate(&facing(Vec3(0f32, -1f32, 0f32))), 0f32);
+        let blended = triplanar.evaluate(&facing(Vec3(1f32, 1f32, 0f32)));
+        assert!((blended - 0.5f32).abs() < 0.00042f32);
+    }
+
+    #[test]
+    fn test_normal_and_bump_maps() {
+        let up = Direction {
+            vec: Vec3(0f32, 0f32, 1f32),
+        };
+        let tangent = Direction {
+            vec: Vec3(1f32, 0f32, 0f32),
+        };
+        let context = TextureContext {
+            position: Position::new(0f32, 0f32, 0f32),
+            uv: (0.3f32, 0.4f32),
+            duv_dx: (0f32, 0f32),
+            duv_dy: (0f32, 0f32),
+            normal: up,
+        };
+        let material = Rc::new(CookTorrance {
+            albedo: LdrColor::new(0.5f32, 0.5f32, 0.5f32),
+            roughness_u: 0.5f32,
+            roughness_v: 0.5f32,
+            f0: F0_NORMAL,
+            thin_film: None,
+        });
+
+        let flat = NormalMap {
+            material: material.clone(),
+            normals: Box::new(Constant(HdrColor::new(0.5f32, 0.5f32, 1f32))),
+            strength: 1f32,
+        };
+        assert!(flat.shading_normal(&context, tangent).cos_angle_between(up) > 0.9999f32);
+        let tilted = NormalMap {
+            material: material.clone(),
+            normals: Box::new(Constant(HdrColor::new(1f32, 0.5f32, 1f32))),
+            strength: 1f32,
+        };
+        let normal = tilted.shading_normal(&context, tangent);
+        assert!((normal.vec.0 - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.001f32);
+
+        // A ramp rising by one along u tilts the normal back by 45 degrees.
+        let ramp = BumpMap {
+            material: material.clone(),
+            height: Box::new(Gradient {
+                from: 0f32,
+                to: 10f32,
+                origin: Position::new(-5f32, 0f32, 0f32),
+                direction: tangent,
+                length: 10f32,
+            }),
+            scale: 1f32,
+        };
+        let normal = ramp.shading_normal(&context, tangent);
+        assert!((normal.vec.0 + std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01f32);
+
+        // Wrapped and bare BSDFs hand out the one they hold.
+        let shared: Rc<dyn Bsdf> = material.clone();
+        assert!(Rc::ptr_eq(&Rc::new(ramp).bsdf(&context), &shared));
+        assert!(Rc::ptr_eq(&material.clone().bsdf(&context), &shared));
+    }
+}
@@ -1,15 +1,19 @@
 extern crate bmp;
 
 use std::io::Error;
+use std::rc::Rc;
 
 use bmp::{Image, Pixel};
 use project_1eb_reference_core::base_types::{HdrColor, LdrColor, Position, Transform};
-use project_1eb_reference_core::scene::{Material, PerspectiveCamera, Plane, Scene, F0_GOLD};
-use project_1eb_reference_core::{render_hdr_image, render_ldr_image};
+use project_1eb_reference_core::bsdf::F0_GOLD;
+use project_1eb_reference_core::principled::{Principled, PrincipledParameters};
+use project_1eb_reference_core::scene::{ObjectId, PerspectiveCamera, Plane, Scene};
+use project_1eb_reference_core::{render_hdr_image, render_ldr_image, Whitted};
 
 const GAMMA: f32 = 2.2f32;
 const EXPOSURE: f32 = 1.0f32;
 const SQRT_SUPER_SAMPLING_RATE: usize = 1;
+const SAMPLES_PER_PIXEL: usize = 1;
 const WIDTH: usize = 900;
 const HEIGHT: usize = 540;
 
@@ -24,31 +28,29 @@ fn main() -> Result<(), Error> {
             1f32,
         )),
         Box::new(Plane::new(
+            ObjectId(0),
             Transform::I,
             1f32,
             0f32,
             0f32,
             1f32,
-            Material {
-                albedo: LdrColor {
-                    r: 1f32,
-                    g: 1f32,
-                    b: 0.2f32,
-                },
+            Rc::new(Principled::new(PrincipledParameters {
+                base_color: F0_GOLD,
+                metallic: 1f32,
                 roughness: 0.42f32,
-                f0: F0_GOLD,
-            },
+                ..Default::default()
+            })),
         )),
-        HdrColor {
+        Box::new(HdrColor {
             r: 1f32,
             g: 1f32,
             b: 1f32,
-        },
+        }),
         vec![],
     );
 
     let output = render_ldr_image(
-        render_hdr_image(&scene, WIDTH, HEIGHT),
+        render_hdr_image(&scene, &Whitted, WIDTH, HEIGHT, SAMPLES_PER_PIXEL),
         EXPOSURE,
         GAMMA,
         SQRT_SUPER_SAMPLING_RATE,